@@ -17,12 +17,12 @@ pub fn main() {
 		match result.unwrap() {
 			None => ::std::thread::sleep(::std::time::Duration::from_millis(500)),
 			Some(mut client) => {
-				if client.request().is_none() {
+				if client.next_request().is_err() || client.request().is_none() {
 					println!("Client {} didn't send any request", client.addr());
 					client.respond_ok("No request :(".as_bytes())
 						.expect("Could not send data to client!");
 				} else {
-					let request_copy = client.request().as_ref().unwrap().clone();
+					let request_copy = client.request().as_ref().unwrap().path().to_owned();
 
 					println!("Client {} requested {}, echoing...", client.addr(), request_copy);
 					client.respond_ok(request_copy.as_bytes())
@@ -0,0 +1,278 @@
+use std::{
+	io::{self, Read, Write, BufRead, BufReader},
+	cmp
+};
+use crate::client::{Headers, Version};
+use crate::error::RequestError;
+use crate::limits::Limits;
+use crate::transport::Transport;
+
+// The longest line allowed for the size of a chunk, including extensions
+const MAX_CHUNK_LINE: usize = 1024;
+
+// How the end of a request body is determined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+	// The body has been read completely (or there is none)
+	Done,
+	// The body ends after this many more bytes
+	Length(usize),
+	// The body is chunked, and this many bytes are left in the current
+	// chunk. If 0, the size of the next chunk has to be read first.
+	Chunked(usize),
+	// Reading the body failed, so its end is unknown
+	Failed
+}
+
+// The state of reading the body of the current request
+#[derive(Debug)]
+pub(crate) struct BodyState {
+	framing: Framing,
+	// Whether the client waits for "100 Continue" before sending the body
+	expect_continue: bool,
+	bytes_read: usize,
+	max_size: usize,
+	// The maximum size of the trailers after the last chunk
+	max_trailer_size: usize
+}
+
+impl BodyState {
+	pub(crate) fn none() -> Self {
+		BodyState {
+			framing: Framing::Done,
+			expect_continue: false,
+			bytes_read: 0,
+			max_size: 0,
+			max_trailer_size: 0
+		}
+	}
+
+	// Determine how the body of a request with the given headers is framed
+	pub(crate) fn new(headers: &Headers, version: Version, limits: &Limits) -> Result<Self, RequestError> {
+		let framing = if headers.contains("transfer-encoding") {
+			// Chunked has to be the last encoding, otherwise the end of
+			// the body can't be determined. A length as well could be
+			// read differently by a proxy in front of the server, which
+			// would then take part of the body for another request.
+			let is_chunked = headers.get_list("transfer-encoding").last()
+				.map(|e| e.eq_ignore_ascii_case("chunked"))
+				.unwrap_or(false);
+			if !is_chunked || version == Version::HTTP10 || headers.contains("content-length") {
+				return Err(RequestError::BadRequest);
+			}
+			Framing::Chunked(0)
+		} else if headers.contains("content-length") {
+			// Repeated lengths are only acceptable if they are all the same
+			let mut lengths = headers.get_list("content-length").map(|l| parse_number(l, 10));
+			let length = match lengths.next() {
+				Some(Some(length)) => length,
+				_ => return Err(RequestError::BadRequest)
+			};
+			if !lengths.all(|l| l == Some(length)) {
+				return Err(RequestError::BadRequest);
+			}
+			if length > limits.max_body_size {
+				return Err(RequestError::PayloadTooLarge);
+			}
+			if length == 0 { Framing::Done } else { Framing::Length(length) }
+		} else {
+			Framing::Done
+		};
+
+		let expect_continue = framing != Framing::Done
+			&& version == Version::HTTP11
+			&& headers.contains_token("expect", "100-continue");
+
+		Ok(BodyState {
+			framing,
+			expect_continue,
+			bytes_read: 0,
+			max_size: limits.max_body_size,
+			max_trailer_size: limits.max_header_size
+		})
+	}
+
+	// Whether the whole body has been read
+	pub(crate) fn is_done(&self) -> bool {
+		self.framing == Framing::Done
+	}
+
+	// Whether the client is still waiting for permission to send the body.
+	// If so, the body can't be skipped, since it may never arrive.
+	pub(crate) fn is_waiting_for_continue(&self) -> bool {
+		self.expect_continue && !self.is_done()
+	}
+
+	// Whether the end of the body can't be found, so the connection can't be
+	// used for another request.
+	pub(crate) fn prevents_reuse(&self) -> bool {
+		self.is_waiting_for_continue() || self.framing == Framing::Failed
+	}
+}
+
+/// A reader for the body of a request, obtained from ``Client::body``.
+///
+/// The body ends where the ``Content-Length`` header says it does, and
+/// ``Transfer-Encoding: chunked`` bodies are decoded while reading. If the
+/// client sent ``Expect: 100-continue``, it is told to continue sending the
+/// body on the first read.
+///
+/// Reading fails with an ``std::io::Error`` wrapping a ``RequestError`` (see
+/// ``RequestError::from_io_error``) if the body is malformed or larger than
+/// allowed by the ``Limits``.
+///
+/// Parts of the body which are not read are skipped before the next request
+/// is read.
+#[derive(Debug)]
+pub struct Body<'a> {
+	reader: &'a mut BufReader<Box<dyn Transport>>,
+	state: &'a mut BodyState
+}
+
+impl<'a> Body<'a> {
+	pub(crate) fn new(
+		reader: &'a mut BufReader<Box<dyn Transport>>,
+		state: &'a mut BodyState) -> Self
+	{
+		Body {
+			reader,
+			state
+		}
+	}
+
+	// Tell the client to send the body, if it is waiting for that
+	fn send_continue(&mut self) -> io::Result<()> {
+		if self.state.is_waiting_for_continue() {
+			let stream = self.reader.get_mut();
+			stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+			stream.flush()?;
+		}
+		self.state.expect_continue = false;
+		Ok(())
+	}
+
+	// Read the size of the next chunk and skip the trailers after the last one.
+	// Returns the size of the chunk.
+	fn read_chunk_size(&mut self) -> io::Result<usize> {
+		let line = read_line(self.reader, MAX_CHUNK_LINE)?;
+		let line = String::from_utf8_lossy(&line);
+		let size = line.split(';').next().unwrap_or("").trim();
+		let size = parse_number(size, 16)
+			.ok_or_else(|| io::Error::from(RequestError::BadRequest))?;
+
+		if size == 0 {
+			// Skip trailers up to the empty line ending the body, which may
+			// be as large as the headers
+			let mut trailer_size = 0;
+			loop {
+				let line = read_line(self.reader, self.state.max_trailer_size - trailer_size)?;
+				if line.iter().all(u8::is_ascii_whitespace) {
+					break;
+				}
+				trailer_size += line.len();
+			}
+		}
+
+		Ok(size)
+	}
+
+	// Skip the line ending after the data of a chunk
+	fn read_chunk_end(&mut self) -> io::Result<()> {
+		let line = read_line(self.reader, 2)?;
+		if line.iter().all(u8::is_ascii_whitespace) {
+			Ok(())
+		} else {
+			Err(RequestError::BadRequest.into())
+		}
+	}
+
+	fn read_framed(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if buf.is_empty() || self.state.is_done() {
+			return Ok(0);
+		}
+		self.send_continue()?;
+
+		let remaining = match self.state.framing {
+			Framing::Done => return Ok(0),
+			Framing::Failed => return Err(io::Error::from(io::ErrorKind::InvalidData)),
+			Framing::Length(remaining) => remaining,
+			Framing::Chunked(0) => match self.read_chunk_size()? {
+				0 => {
+					self.state.framing = Framing::Done;
+					return Ok(0);
+				},
+				size => size
+			},
+			Framing::Chunked(remaining) => remaining
+		};
+
+		if self.state.bytes_read + cmp::min(remaining, buf.len()) > self.state.max_size {
+			return Err(RequestError::PayloadTooLarge.into());
+		}
+
+		let length = cmp::min(remaining, buf.len());
+		let bytes_read = self.reader.read(&mut buf[..length])?;
+		if bytes_read == 0 {
+			return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+		}
+		self.state.bytes_read += bytes_read;
+		let remaining = remaining - bytes_read;
+
+		self.state.framing = match self.state.framing {
+			Framing::Length(_) if remaining == 0 => Framing::Done,
+			Framing::Length(_) => Framing::Length(remaining),
+			_ => {
+				if remaining == 0 {
+					self.read_chunk_end()?;
+				}
+				Framing::Chunked(remaining)
+			}
+		};
+
+		Ok(bytes_read)
+	}
+}
+
+impl Read for Body<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let result = self.read_framed(buf);
+		if result.is_err() {
+			self.state.framing = Framing::Failed;
+		}
+		result
+	}
+}
+
+// Read a line (including the line ending) of at most max_len bytes
+fn read_line(reader: &mut BufReader<Box<dyn Transport>>, max_len: usize) -> io::Result<Vec<u8>> {
+	let mut line = Vec::new();
+	loop {
+		let buffer = reader.fill_buf()?;
+		if buffer.is_empty() {
+			return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+		}
+
+		let (length, is_done) = match buffer.iter().position(|&b| b == b'\n') {
+			Some(i) => (i + 1, true),
+			None => (buffer.len(), false)
+		};
+		if line.len() + length > max_len {
+			return Err(RequestError::BadRequest.into());
+		}
+		line.extend_from_slice(&buffer[..length]);
+		reader.consume(length);
+
+		if is_done {
+			return Ok(line);
+		}
+	}
+}
+
+// Parse a number of digits in the given radix, without a sign or whitespace
+// (which ``from_str_radix`` would accept)
+fn parse_number(s: &str, radix: u32) -> Option<usize> {
+	if s.is_empty() || !s.chars().all(|c| c.is_digit(radix)) {
+		return None;
+	}
+	usize::from_str_radix(s, radix).ok()
+}
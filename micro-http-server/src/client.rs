@@ -1,173 +1,687 @@
 use std::{
 	io::{self, Read, Write, BufRead, BufReader},
-	net::{SocketAddr, TcpStream},
-	collections::HashMap,
+	net::SocketAddr,
+	fmt,
+	time::{Duration, Instant, SystemTime},
 	str
 };
 use urlencoding::decode;
+use crate::body::{Body, BodyState};
+use crate::error::{RequestError, ReadError};
+use crate::limits::Limits;
+use crate::media_type::{MediaType, is_token};
+use crate::multipart::Multipart;
+use crate::header_map::HeaderMap;
+use crate::response::{Response, ResponseBody};
+use crate::status::StatusCode;
+use crate::query::QueryData;
+use crate::transport::{Transport, Untimed};
+use crate::memory::{MemoryStream, ResponseCapture};
+use crate::listener::ClientAddr;
+#[cfg(all(feature = "event-loop", unix))]
+use std::{mem, os::unix::io::RawFd};
+#[cfg(all(feature = "event-loop", unix))]
+use crate::event_loop::LoopLink;
+
+// The value of the "Server" header, unless a response sets its own
+const SERVER: &str = "micro_http_server";
+// How many empty lines may precede a request line
+const MAX_EMPTY_LINES: usize = 4;
+// How long to read what a client still sends after rejecting it, and how
+// much of it
+const LINGER_TIME: Duration = Duration::from_secs(1);
+const MAX_LINGER_SIZE: usize = 64 * 1024;
 // use super::os_windows;
 
 /// The URL of a request, represented as a String after
 /// decoding the percent-encoded path in a request header
 pub type URL = String;
 
-/// The headers of a request, represented as a mapping
-/// of String keys to String values.
-pub type Headers = HashMap<String, String>;
+/// The headers of a request. Names are compared case-insensitively but keep
+/// the case the client sent them in, and repeated fields are kept in order.
+pub type Headers = HeaderMap;
 
-/// An HTTP request from a client. Currently, only
-/// GET and POST are supported.
-#[derive(Debug)]
-pub enum Request {
-	/// A GET request which has query data and headers
-	GET(QueryData, Headers),
-	/// A POST request which has headers and the data
-	/// from its body
-	POST(Headers, Option<FormData>)
+/// The method of a request. Currently, only GET, HEAD, POST and OPTIONS
+/// are supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+	/// A GET request, which may have query data
+	GET,
+	/// A HEAD request. It should be handled the same as a GET request,
+	/// the body of the response is left out automatically.
+	HEAD,
+	/// A POST request. Its body can be read with ``Client::form_data``
+	/// or ``Client::body``.
+	POST,
+	/// An OPTIONS request. Its target may be ``*`` to refer to the server
+	/// as a whole.
+	OPTIONS
+}
+
+impl Method {
+	/// Return the name of the method as it appears in a request, e.g. ``GET``.
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Method::GET => "GET",
+			Method::HEAD => "HEAD",
+			Method::POST => "POST",
+			Method::OPTIONS => "OPTIONS"
+		}
+	}
+}
+
+impl fmt::Display for Method {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.as_str())
+	}
 }
 
-/// The query data encoded in a request URL.
-pub type QueryData = HashMap<String, String>;
+/// An HTTP request from a client, without its body (see ``Client::body``).
+#[derive(Debug, Clone)]
+pub struct Request {
+	method: Method,
+	version: Version,
+	target: String,
+	path: URL,
+	query_string: Option<String>,
+	query: QueryData,
+	headers: Headers
+}
+
+impl Request {
+	/// Return the method of the request.
+	pub fn method(&self) -> Method {
+		self.method
+	}
+
+	/// Return the HTTP version the request was made with.
+	pub fn version(&self) -> Version {
+		self.version
+	}
+
+	/// Return the request target exactly as the client sent it, e.g.
+	/// ``/a%20b?tag=x`` or ``http://example.com/a%20b?tag=x``.
+	pub fn target(&self) -> &str {
+		&self.target
+	}
+
+	/// Return the percent-decoded path of the request, e.g. ``/a b``. This is
+	/// ``*`` for an OPTIONS request to the server as a whole.
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	/// Return the path of the request as the client sent it, without the
+	/// scheme and host of an absolute URL and without the query string, e.g.
+	/// ``/a%20b``.
+	pub fn raw_path(&self) -> &str {
+		let target = match self.target.split_once("://") {
+			Some((_, rest)) => &rest[rest.find('/').unwrap_or(rest.len())..],
+			None => &self.target
+		};
+		target.split('?').next().unwrap_or("")
+	}
+
+	/// Return the query string as the client sent it, without the ``?``, or
+	/// ``None`` if the target has no ``?``.
+	pub fn query_string(&self) -> Option<&str> {
+		self.query_string.as_deref()
+	}
+
+	/// Return the decoded query data, in the order it was sent.
+	pub fn query(&self) -> &QueryData {
+		&self.query
+	}
+
+	/// Return the headers of the request.
+	pub fn headers(&self) -> &Headers {
+		&self.headers
+	}
+}
+
+/// The version of the HTTP protocol a request was made with.
+///
+/// Requests which do not name a version (i.e. HTTP/0.9 style requests)
+/// are treated as HTTP/1.0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+	/// HTTP/1.0, connections are closed after each response unless
+	/// the client asks for ``Connection: keep-alive``
+	HTTP10,
+	/// HTTP/1.1, connections are kept open after each response unless
+	/// either side sends ``Connection: close``
+	HTTP11
+}
 
 /// The contents of the body of a form. Can be key-value data,
-/// an arbitrary string, or a handle on the underlying TCP connection.
+/// an arbitrary string, or a parser for a multipart body.
 #[derive(Debug)]
-pub enum FormData {
-	/// Data is key-value pairs
-	KeyVal(HashMap<String, String>),
+pub enum FormData<'a> {
+	/// Data is key-value pairs, from a URL encoded form
+	KeyVal(QueryData),
 	/// Data is plain text
 	Text(String),
-	/// Data is a stream of bytes
-	Stream(BufReader<TcpStream>)
+	/// Data is a ``multipart/form-data`` body, whose parts (fields and
+	/// uploaded files) are read one after another
+	Multipart(Multipart<Body<'a>>)
 }
 
-/// This struct represents a client which has connected to the µHTTP server.microhttp
+/// This struct represents a client which has connected to the µHTTP server.
+///
+/// A client may make several requests over the same connection (HTTP/1.1
+/// persistent connections). After responding, call ``next_request`` to
+/// wait for the next one, as long as ``keep_alive`` returns ``true``.
 ///
 /// If an instance of this struct is dropped, the connection is closed.
 #[derive(Debug)]
 pub struct Client {
-	stream: TcpStream,
-	addr: SocketAddr,
-	request: Option<(URL, Request)>
+	reader: BufReader<Box<dyn Transport>>,
+	addr: ClientAddr,
+	// The other end of the connection, which is a proxy if it differs from addr
+	peer_addr: ClientAddr,
+	limits: Limits,
+	request: Option<Request>,
+	request_error: Option<RequestError>,
+	body: BodyState,
+	version: Version,
+	keep_alive: bool,
+	is_head: bool,
+	// Whether sending a response to the current request has started
+	has_responded: bool,
+	// The event loop the client is given back to when it is dropped
+	#[cfg(all(feature = "event-loop", unix))]
+	event_loop: Option<LoopLink>,
+	// The body of the last response, which the event loop sends
+	#[cfg(all(feature = "event-loop", unix))]
+	deferred: Option<DeferredBody>
 }
 
-fn read_request_type(reader: &mut BufReader<TcpStream>) -> io::Result<String> {
-	let mut buffer = Vec::new();
-	reader.read_until(b' ', &mut buffer)?;
-	buffer.pop();
-	Ok(String::from_utf8_lossy(&buffer).to_string())
+fn is_timeout(e: &io::Error) -> bool {
+	matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
 }
 
-fn read_request_url(reader: &mut BufReader<TcpStream>) -> io::Result<(URL, QueryData)> {
-	let mut buffer = Vec::new();
-	reader.read_until(b' ', &mut buffer)?;
-	buffer.pop();
-	let url = String::from_utf8_lossy(&buffer).to_string();
-	let (url, query) = match url.split_once('?') {
-		Some((url_string, query_string)) => {
-			(url_string, parse_url_encoded_key_value_pairs(query_string))
-		},
-		None => (url.as_str(), QueryData::new())
+fn parse_version(version: Option<&str>) -> Result<Version, RequestError> {
+	match version {
+		Some("HTTP/1.0") | None => Ok(Version::HTTP10),
+		Some(v) => {
+			let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+			match v.strip_prefix("HTTP/").and_then(|v| v.split_once('.')) {
+				Some((major, minor)) if is_number(major) && is_number(minor) => match major {
+					"1" => Ok(Version::HTTP11),
+					_ => Err(RequestError::HTTPVersionNotSupported)
+				},
+				_ => Err(RequestError::BadRequest)
+			}
+		}
+	}
+}
+
+// Split a request target into its decoded path and its raw query string
+fn parse_request_target(target: &str) -> Result<(URL, Option<String>), RequestError> {
+	// Requests may contain an absolute URL, of which only the path is used
+	let url = match target.split_once("://") {
+		Some((_, rest)) => &rest[rest.find('/').unwrap_or(rest.len())..],
+		None => target
 	};
-	let url = decode(url)
-		.map_err(|_| io::Error::from(io::ErrorKind::Other))?
+	if !url.starts_with('/') {
+		return Err(RequestError::BadRequest);
+	}
+
+	let (path, query_string) = match url.split_once('?') {
+		Some((path, query_string)) => (path, Some(query_string.to_owned())),
+		None => (url, None)
+	};
+	let path = decode(path)
+		.map_err(|_| RequestError::BadRequest)?
 		.to_string();
-	Ok((URL::from(url), query))
+	Ok((URL::from(path), query_string))
 }
 
-fn parse_url_encoded_key_value_pairs(s: &str) -> HashMap<String, String> {
-	s.trim().split('&').filter_map(
-		|pair| {
-			match pair.split_once('=') {
-				Some((k, v)) => {
-					let k = decode(k).ok()?.to_string();
-					let v = decode(v).ok()?.to_string();
-					Some((k, v))
-				},
-				None => Some((pair.to_string(), String::new()))
-			}
-		})
-	.collect()
+// Remove the "\n" or "\r\n" at the end of a line
+fn strip_line_ending(line: &[u8]) -> &[u8] {
+	let line = line.strip_suffix(b"\n").unwrap_or(line);
+	line.strip_suffix(b"\r").unwrap_or(line)
 }
 
-fn read_request_headers(reader: &mut BufReader<TcpStream>) -> io::Result<Headers> {
-	let mut headers = Headers::new();
-	// Initialize with non-empty contents so the loop runs at least once
-	let mut buffer = String::from("_");
-	while buffer.trim() != "" {
-		buffer = String::new();
-		reader.read_line(&mut buffer)?;
-		if let Some((k, v)) = buffer.split_once(": ") {
-			headers.insert(k.trim().to_lowercase(), v.trim().to_owned());
-		}
+// Parse a header field line without its line ending. Whitespace is allowed
+// around the colon; the name has to be a token.
+fn parse_header(line: &[u8]) -> Result<(String, String), RequestError> {
+	let colon = line.iter().position(|&b| b == b':').ok_or(RequestError::BadRequest)?;
+	let name = str::from_utf8(&line[..colon])
+		.map_err(|_| RequestError::BadRequest)?
+		.trim_end_matches([' ', '\t']);
+	if !is_token(name) {
+		return Err(RequestError::BadRequest);
+	}
+	Ok((name.to_owned(), parse_header_value(&line[colon + 1..])?))
+}
+
+// Parse the value of a header field, which must not contain control characters
+// other than tabs. Bytes which aren't valid UTF-8 are replaced.
+fn parse_header_value(value: &[u8]) -> Result<String, RequestError> {
+	if value.iter().any(|&b| (b < 0x20 && b != b'\t') || b == 0x7F) {
+		return Err(RequestError::BadRequest);
 	}
-	Ok(headers)
+	Ok(String::from_utf8_lossy(value).trim_matches([' ', '\t']).to_owned())
 }
 
-fn read_form_content_to_string(mut reader: BufReader<TcpStream>, headers: &Headers) -> Option<String> {
-	if let Some(length) = headers.get("content-length") {
-		let length = length.parse().ok()?;
-		reader.get_mut().set_nonblocking(true).ok()?;
-		let mut buffer = vec![0; length];
-		reader.read_exact(&mut buffer).ok()?;
-		return Some(String::from_utf8(buffer).ok()?);
+// Check whether the connection should stay open after responding, based on
+// the request's version and its "Connection" header.
+fn wants_keep_alive(version: Version, headers: &Headers) -> bool {
+	let has_option = |option: &str| headers.contains_token("connection", option);
+
+	match version {
+		Version::HTTP10 => has_option("keep-alive"),
+		Version::HTTP11 => !has_option("close")
 	}
-	None
 }
 
-fn read_form_data(reader: BufReader<TcpStream>, headers: &Headers) -> io::Result<Option<FormData>> {
-	match headers.get("content-type").map(|s| s.as_str()) {
-		Some("text/plain") => {
-			Ok(read_form_content_to_string(reader, headers).map(|text| FormData::Text(text)))
-		},
-		Some("application/x-www-form-urlencoded") => {
-			Ok(read_form_content_to_string(reader, headers).map(|data| {
-				FormData::KeyVal(parse_url_encoded_key_value_pairs(&data))
-			}))
-		},
-		Some("multipart/form-data") => {
-			Ok(Some(FormData::Stream(reader)))
-		},
-		_ => Ok(None)
+// Build a response from a status line like "200 OK" and header lines like
+// "Cache-Control: no-cache", as they are passed to the respond* functions.
+fn legacy_response<'a>(status_code: &str, headers: &[String]) -> io::Result<Response<'a>> {
+	let mut response = Response::new(status_code.parse()?);
+	for header in headers {
+		let (name, value) = header.split_once(':').ok_or_else(|| io::Error::new(
+			io::ErrorKind::InvalidInput, format!("Invalid header: {}", header)))?;
+		response.headers_mut().append(name.trim(), value.trim());
 	}
+	Ok(response)
+}
+
+fn read_body_to_string(mut body: Body) -> io::Result<Option<String>> {
+	let mut buffer = Vec::new();
+	body.read_to_end(&mut buffer)?;
+	Ok(String::from_utf8(buffer).ok())
 }
 
 impl Client {
-	pub(crate) fn new(stream: TcpStream, addr: SocketAddr) -> Result<Client,::std::io::Error> {
-		let mut reader = BufReader::new(stream.try_clone()?);
-		let request_type = read_request_type(&mut reader)?;
-		let request = match request_type.as_str() {
-			"GET" => {
-				let (url, query) = read_request_url(&mut reader)?;
-				let headers = read_request_headers(&mut reader)?;
-				Some((url, Request::GET(query, headers)))
+	pub(crate) fn new(stream: Box<dyn Transport>, addr: ClientAddr, limits: Limits) -> Result<Client,::std::io::Error> {
+		stream.set_write_timeout(limits.write_timeout)?;
+		Ok(Client::with_transport(stream, addr, limits))
+	}
+
+	/// Create a client which communicates over any stream, for example one
+	/// accepted by another server or a pipe.
+	///
+	/// Since arbitrary streams don't support timeouts, only the size limits
+	/// of ``limits`` apply.
+	pub fn from_stream<S>(stream: S, addr: ClientAddr, limits: Limits) -> Client
+	where S: Read + Write + Send + 'static
+	{
+		Client::with_transport(Box::new(Untimed(stream)), addr, limits)
+	}
+
+	/// Create a client which reads its requests from ``input`` and captures
+	/// everything sent in response, e.g. to test request handlers without
+	/// opening a socket. The client's address is ``127.0.0.1:0``.
+	///
+	/// # Example
+	///
+	/// ```
+	/// use micro_http_server::{Client, Response};
+	///
+	/// let (mut client, capture) = Client::in_memory("GET /cat.txt HTTP/1.1\r\n\r\n");
+	/// assert!(client.next_request().unwrap());
+	/// assert_eq!("/cat.txt", client.request().as_ref().unwrap().path());
+	/// client.send(Response::ok().body("Cats are nice.".as_bytes())).unwrap();
+	///
+	/// let response = String::from_utf8(capture.bytes()).unwrap();
+	/// assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+	/// assert!(response.ends_with("\r\n\r\nCats are nice."));
+	/// ```
+	pub fn in_memory(input: impl Into<Vec<u8>>) -> (Client, ResponseCapture) {
+		let (stream, capture) = MemoryStream::new(input.into());
+		let addr = ClientAddr::Inet(SocketAddr::from(([127, 0, 0, 1], 0)));
+		(Client::with_transport(Box::new(stream), addr, Limits::default()), capture)
+	}
+
+	fn with_transport(stream: Box<dyn Transport>, addr: ClientAddr, limits: Limits) -> Client {
+		// Responses are written through the reader, which doesn't buffer writes
+		let reader = BufReader::new(stream);
+		Client {
+			reader,
+			peer_addr: addr.clone(),
+			addr,
+			limits,
+			request: None,
+			request_error: None,
+			body: BodyState::none(),
+			version: Version::HTTP10,
+			keep_alive: false,
+			is_head: false,
+			has_responded: false,
+			#[cfg(all(feature = "event-loop", unix))]
+			event_loop: None,
+			#[cfg(all(feature = "event-loop", unix))]
+			deferred: None
+		}
+	}
+
+	/// Wait for the client to send a request and read it, replacing the
+	/// previous request (if any).
+	///
+	/// This must be called once before the first request can be accessed, and
+	/// again for every following request on the same connection. Requests which
+	/// are pipelined (sent before the previous response has been received) are
+	/// read from where the previous one ended.
+	///
+	/// Returns ``false`` if the client closed the connection instead of sending
+	/// a request. An ``std::io::Error`` only concerns this client's connection;
+	/// a request which could be read but is invalid is reported through
+	/// ``request_error`` instead.
+	///
+	/// After the first request, this should only be called if ``keep_alive``
+	/// returns ``true``.
+	pub fn next_request(&mut self) -> io::Result<bool> {
+		#[cfg(all(feature = "event-loop", unix))]
+		self.finish_deferred()?;
+		// Skip whatever is left of the previous request's body
+		io::copy(&mut self.body(), &mut io::sink())?;
+
+		self.request = None;
+		self.request_error = None;
+		self.keep_alive = false;
+		self.has_responded = false;
+
+		// Wait for the request to start
+		self.reader.get_ref().set_read_timeout(self.limits.idle_timeout)?;
+		let is_closed = self.reader.fill_buf()?.is_empty();
+		// A trusted proxy names the client in front of the first request
+		if let Some(source) = self.reader.get_mut().take_source() {
+			self.addr = source;
+		}
+		if is_closed {
+			return Ok(false);
+		}
+
+		// After an invalid request, keep_alive stays false since we can't
+		// tell whether a body follows, so we cannot find the start of the
+		// next request.
+		match self.read_request() {
+			Ok(request) => self.request = Some(request),
+			Err(ReadError::Request(e)) => self.request_error = Some(e),
+			Err(ReadError::Io(e)) => return Err(e)
+		}
+
+		Ok(true)
+	}
+
+	fn read_request(&mut self) -> Result<Request, ReadError> {
+		let deadline = self.limits.header_read_timeout.map(|t| Instant::now() + t);
+
+		// Skip a few empty lines preceding the request line, which count
+		// towards the size of the headers
+		let mut header_size = 0;
+		let mut empty_lines = 0;
+		let line = loop {
+			let buffer = self.read_head_line(
+				deadline, self.limits.max_request_line, RequestError::URITooLong)?;
+			if buffer.is_empty() {
+				return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+			}
+			let line = String::from_utf8_lossy(&buffer).into_owned();
+			if !line.trim().is_empty() {
+				break line;
+			}
+			empty_lines += 1;
+			header_size += buffer.len();
+			if empty_lines > MAX_EMPTY_LINES {
+				return Err(RequestError::BadRequest.into());
+			}
+			if header_size > self.limits.max_header_size {
+				return Err(RequestError::RequestHeaderFieldsTooLarge.into());
+			}
+		};
+
+		let mut headers = Headers::new();
+		// The last field read, which may continue on the following lines
+		let mut field: Option<(String, String)> = None;
+		loop {
+			let max_len = self.limits.max_header_size.saturating_sub(header_size);
+			let buffer = self.read_head_line(
+				deadline, max_len, RequestError::RequestHeaderFieldsTooLarge)?;
+			// The connection was closed before the end of the headers
+			if !buffer.ends_with(b"\n") {
+				return Err(RequestError::BadRequest.into());
+			}
+			let line = strip_line_ending(&buffer);
+			if line.is_empty() {
+				break;
+			}
+			header_size += buffer.len();
+
+			// A line starting with whitespace continues the previous field
+			// (obsolete line folding), which is the same as a single space.
+			if line[0] == b' ' || line[0] == b'\t' {
+				let (_, value) = field.as_mut().ok_or(RequestError::BadRequest)?;
+				let continuation = parse_header_value(line)?;
+				if !continuation.is_empty() {
+					value.push(' ');
+					value.push_str(&continuation);
+				}
+				continue;
+			}
+
+			if headers.len() + usize::from(field.is_some()) >= self.limits.max_headers {
+				return Err(RequestError::RequestHeaderFieldsTooLarge.into());
+			}
+			if let Some((k, v)) = field.replace(parse_header(line)?) {
+				headers.append(k, v);
+			}
+		}
+		if let Some((k, v)) = field {
+			headers.append(k, v);
+		}
+
+		// The body is read while the request is being handled
+		self.reader.get_ref().set_read_timeout(self.limits.idle_timeout)?;
+
+		self.parse_request(line.trim(), headers)
+	}
+
+	// Read a line of the request line or headers (including the line ending),
+	// failing with ``too_long`` if it is longer than ``max_len`` bytes. Returns
+	// the incomplete line if the connection was closed.
+	fn read_head_line(
+		&mut self, deadline: Option<Instant>,
+		max_len: usize, too_long: RequestError) -> Result<Vec<u8>, ReadError>
+	{
+		let mut line = Vec::new();
+		loop {
+			if let Some(deadline) = deadline {
+				let remaining = deadline.saturating_duration_since(Instant::now());
+				if remaining.is_zero() {
+					return Err(RequestError::RequestTimeout.into());
+				}
+				self.reader.get_ref().set_read_timeout(Some(remaining))?;
+			}
+
+			let buffer = match self.reader.fill_buf() {
+				Ok(buffer) => buffer,
+				Err(e) if is_timeout(&e) => return Err(RequestError::RequestTimeout.into()),
+				Err(e) => return Err(e.into())
+			};
+			if buffer.is_empty() {
+				return Ok(line);
+			}
+
+			let (length, is_done) = match buffer.iter().position(|&b| b == b'\n') {
+				Some(i) => (i + 1, true),
+				None => (buffer.len(), false)
+			};
+			if line.len() + length > max_len {
+				return Err(too_long.into());
+			}
+			line.extend_from_slice(&buffer[..length]);
+			self.reader.consume(length);
+
+			if is_done {
+				return Ok(line);
+			}
+		}
+	}
+
+	fn parse_request(&mut self, line: &str, headers: Headers) -> Result<Request, ReadError> {
+		let parts: Vec<&str> = line.split_whitespace().collect();
+		let (method, target, version) = match parts[..] {
+			[method, target] => (method, target, None),
+			[method, target, version] => (method, target, Some(version)),
+			_ => return Err(RequestError::BadRequest.into())
+		};
+		self.version = parse_version(version)?;
+		self.is_head = method == "HEAD";
+		self.body = BodyState::new(&headers, self.version, &self.limits)?;
+		let keep_alive = wants_keep_alive(self.version, &headers);
+
+		let method = match method {
+			"GET" => Method::GET,
+			"HEAD" => Method::HEAD,
+			"POST" => Method::POST,
+			"OPTIONS" => Method::OPTIONS,
+			"PUT" | "DELETE" | "PATCH" | "TRACE" | "CONNECT" => {
+				let (path, _) = parse_request_target(target)?;
+				return Err(RequestError::MethodNotAllowed(method.to_owned(), path).into());
 			},
-			"POST" => {
-				let (url, _) = read_request_url(&mut reader)?;
-				let headers = read_request_headers(&mut reader)?;
-				let data = read_form_data(reader, &headers)?;
-				Some((url, Request::POST(headers, data)))
+			_ if is_token(method) => {
+				return Err(RequestError::NotImplemented(method.to_owned()).into());
 			},
-			_ => None
+			_ => return Err(RequestError::BadRequest.into())
+		};
+
+		let (path, query_string) = if method == Method::OPTIONS && target == "*" {
+			(URL::from(target), None)
+		} else {
+			parse_request_target(target)?
 		};
-		Ok(Client {
-			stream: stream,
-			addr: addr,
-			request: request
+		let query = query_string.as_deref().map(QueryData::parse).unwrap_or_default();
+		self.keep_alive = keep_alive;
+
+		Ok(Request {
+			method,
+			version: self.version,
+			target: target.to_owned(),
+			path,
+			query_string,
+			query,
+			headers
 		})
 	}
 
+	/// Return a reader for the body of the current request.
+	///
+	/// The body can only be read once; afterwards, this returns an empty reader.
+	pub fn body(&mut self) -> Body<'_> {
+		Body::new(&mut self.reader, &mut self.body)
+	}
+
+	/// Read the body of the current request as a form, depending on its
+	/// ``Content-Type``, which is given in the request's ``headers``.
+	///
+	/// URL encoded and plain text forms are read completely, multipart forms
+	/// are returned as a parser reading the parts from the body. Parameters
+	/// of the type like ``charset`` are allowed. Returns ``None`` if the body
+	/// has another type or plain text which isn't valid UTF-8.
+	///
+	/// A multipart form without a ``boundary`` results in an error wrapping
+	/// ``RequestError::BadRequest``.
+	pub fn form_data(&mut self, headers: &Headers) -> io::Result<Option<FormData<'_>>> {
+		let media_type = match headers.get("content-type").and_then(MediaType::parse) {
+			Some(media_type) => media_type,
+			None => return Ok(None)
+		};
+
+		let data = match media_type.essence() {
+			"text/plain" => {
+				read_body_to_string(self.body())?.map(FormData::Text)
+			},
+			"application/x-www-form-urlencoded" => {
+				read_body_to_string(self.body())?.map(|data| FormData::KeyVal(QueryData::parse(&data)))
+			},
+			"multipart/form-data" => {
+				let boundary = media_type.param("boundary")
+					.filter(|b| !b.is_empty())
+					.ok_or(RequestError::BadRequest)?;
+				Some(FormData::Multipart(Multipart::new(self.body(), boundary)))
+			},
+			_ => None
+		};
+		Ok(data)
+	}
+
 	/// Return the address of the requesting client, for example "1.2.3.4:9435".
-	pub fn addr(&self) -> SocketAddr {
-		self.addr
+	///
+	/// If the client connected through a trusted proxy (see
+	/// ``MicroHTTP::set_proxy_protocol``), this is the address the proxy
+	/// accepted the connection from, once the first request was read.
+	pub fn addr(&self) -> &ClientAddr {
+		&self.addr
+	}
+
+	/// Return the address of the other end of the connection, which is the
+	/// proxy's for clients connecting through a trusted proxy, and the same
+	/// as ``addr`` otherwise.
+	pub fn peer_addr(&self) -> &ClientAddr {
+		&self.peer_addr
+	}
+
+	/// Return the HTTP version of the current request.
+	pub fn version(&self) -> Version {
+		self.version
+	}
+
+	/// Return whether the connection will be kept open after the response
+	/// to the current request has been sent.
+	///
+	/// This is decided by the request's version and ``Connection`` header,
+	/// and may become ``false`` while responding, e.g. if the length of the
+	/// response body is not known in advance.
+	pub fn keep_alive(&self) -> bool {
+		self.keep_alive
+	}
+
+	/// Close the connection after the response to the current request has
+	/// been sent, e.g. because the server is shutting down. The response tells
+	/// the client so with ``Connection: close``.
+	pub fn close_after_response(&mut self) {
+		self.keep_alive = false;
+	}
+
+	/// Return whether sending a response to the current request has started,
+	/// even if it failed. If handling a request fails before, an error
+	/// response can still be sent; otherwise, the connection should be closed.
+	pub fn has_responded(&self) -> bool {
+		self.has_responded
+	}
+
+	/// Return whether the client was passed on by an ``EventLoop``, which it
+	/// is given back to when dropped. The event loop waits for the next
+	/// request, so only the current one should be handled.
+	pub fn has_event_loop(&self) -> bool {
+		#[cfg(all(feature = "event-loop", unix))]
+		{ self.event_loop.is_some() }
+		#[cfg(not(all(feature = "event-loop", unix)))]
+		{ false }
+	}
+
+	/// Return the limits applied to this client's requests.
+	pub fn limits(&self) -> Limits {
+		self.limits
+	}
+
+	/// Change the limits applied to this client's requests. The new limits
+	/// take effect with the next request.
+	pub fn set_limits(&mut self, limits: Limits) -> io::Result<()> {
+		self.reader.get_ref().set_write_timeout(limits.write_timeout)?;
+		self.limits = limits;
+		Ok(())
 	}
 
 	/// Return the request the client made or None if the client
-	/// didn't make any or an invalid one.
+	/// didn't make any (yet, see ``next_request``) or an invalid one.
 	///
-	/// **Note**: At the moment, only HTTP GET and POST are supported.
-	/// Any other requests will not be collected.
-	pub fn request(&self) -> &Option<(URL, Request)> {
+	/// **Note**: At the moment, only HTTP GET, HEAD, POST and OPTIONS are
+	/// supported. Any other requests will not be collected, see ``request_error``.
+	pub fn request(&self) -> &Option<Request> {
 		&self.request
 	}
 
@@ -175,12 +689,21 @@ impl Client {
 	/// or None if the client didn't make any or made an invalid
 	/// one.
 	///
-	/// **Note**: At the moment, only HTTP GET and POST are supported.
-	/// Any other requests will not be collected.
-	pub fn request_mut(&mut self) -> &mut Option<(URL, Request)> {
+	/// **Note**: At the moment, only HTTP GET, HEAD, POST and OPTIONS are
+	/// supported. Any other requests will not be collected, see ``request_error``.
+	pub fn request_mut(&mut self) -> &mut Option<Request> {
 		&mut self.request
 	}
 
+	/// Return the reason why the current request could not be collected, if
+	/// it was invalid or used an unsupported method.
+	///
+	/// The client should be sent a response with the status code given by
+	/// ``RequestError::status``. The connection will be closed afterwards.
+	pub fn request_error(&self) -> Option<&RequestError> {
+		self.request_error.as_ref()
+	}
+
 	/// Send a HTTP 200 OK response to the client + the provided data.
 	/// The data may be an empty array, for example the following
 	/// implementation echos all requests except "/hello":
@@ -194,7 +717,8 @@ impl Client {
 	/// # let mut connection = ::std::net::TcpStream::connect("127.0.0.1:4000").unwrap();
 	/// # connection.write("GET /\r\n\r\n".as_bytes());
 	/// let mut client = server.next_client().unwrap().unwrap();
-	/// let request_str: String = client.request().as_ref().unwrap().clone();
+	/// client.next_request().unwrap();
+	/// let request_str: String = client.request().as_ref().unwrap().path().to_owned();
 	///
 	/// match request_str.as_ref() {
 	/// 	"/hello" => client.respond_ok(&[]),
@@ -202,7 +726,7 @@ impl Client {
 	/// };
 	/// ```
 	pub fn respond_ok(&mut self, data: &[u8]) -> io::Result<usize> {
-		self.respond_ok_chunked(data, data.len())
+		self.send(Response::ok().body(data))
 	}
 
 	// The test in this doc comment is no_run because it refers to an arbitrary
@@ -221,7 +745,7 @@ impl Client {
 	/// # let mut connection = ::std::net::TcpStream::connect("127.0.0.1:4000").unwrap();
 	/// # connection.write("GET /\r\n\r\n".as_bytes());
 	/// let mut client = server.next_client().unwrap().unwrap();
-	/// client.request();
+	/// client.next_request().unwrap();
 	///
 	/// let mut file_handle = OpenOptions::new()
 	///		.read(true)
@@ -234,28 +758,32 @@ impl Client {
 	///
 	/// ```
 	pub fn respond_ok_chunked(&mut self, data: impl Read, content_size: usize) -> io::Result<usize> {
-		self.respond_chunked("200 OK", data, content_size, &vec!())
+		self.respond_chunked("200 OK", data, content_size, &[])
 	}
 
 	/// Send response data to the client.
 	///
 	/// This is similar to ``respond_ok``, but you may control the details yourself.
 	///
-	/// Consider using ``respond_chunked`` for sending file-backed data.
+	/// Consider using ``respond_chunked`` for sending file-backed data, or
+	/// ``send`` for building the response with typed headers and status.
 	///
 	/// # Parameters
 	/// * ``status_code``: Select the status code of the response, e.g. ``200 OK``.
 	/// * ``data``: Data to transmit. May be empty.
 	/// * ``headers``: Additional headers to add to the response. May be empty.
 	///
-	/// Calling ``respond("200 OK", data, &vec!())`` is the same as calling ``respond_ok(data)``.
+	/// Calling ``respond("200 OK", data, &[])`` is the same as calling ``respond_ok(data)``.
+	///
+	/// Fails with ``std::io::ErrorKind::InvalidInput`` if the status code is
+	/// unknown or a header is not of the form ``Name: value``.
 	pub fn respond(
 		&mut self,
 		status_code: &str,
 		data: &[u8],
-		headers: &Vec<String>) -> io::Result<usize>
+		headers: &[String]) -> io::Result<usize>
 	{
-		self.respond_chunked(status_code, data, data.len(), headers)
+		self.send(legacy_response(status_code, headers)?.body(data))
 	}
 
 	/// Send repsonse data to the client.
@@ -269,33 +797,456 @@ impl Client {
 	/// * ``content_size``: Size of the data to transmit in bytes
 	/// * ``headers``: Additional headers to add to the response. May be empty.
 	///
-	/// Calling ``respond_chunked("200 OK", data, content_size, &vec!())`` is the same as calling
+	/// Calling ``respond_chunked("200 OK", data, content_size, &[])`` is the same as calling
 	/// ``repsond_ok_chunked(data, content_size)``.
+	///
+	/// If ``content_size`` is ``usize::MAX``, the size is considered unknown and the
+	/// response is sent the same way as with ``respond_stream``.
+	///
+	/// If the request was a HEAD request, ``data`` is not sent (or read).
 	pub fn respond_chunked(
 		&mut self,
 		status_code: &str,
-		mut data: impl Read,
+		data: impl Read,
 		content_size: usize,
-		headers: &Vec<String>) -> io::Result<usize> 
+		headers: &[String]) -> io::Result<usize>
+	{
+		let response = legacy_response(status_code, headers)?;
+		if content_size == usize::MAX {
+			self.send(response.body_stream(data))
+		} else {
+			self.send(response.body_reader(data, content_size))
+		}
+	}
+
+	/// Send a HTTP 200 OK response to the client + the provided data, whose
+	/// size is not known in advance.
+	///
+	/// See ``respond_stream`` for details.
+	pub fn respond_ok_stream(&mut self, data: impl Read) -> io::Result<usize> {
+		self.send(Response::ok().body_stream(data))
+	}
+
+	/// Send response data of unknown size to the client.
+	///
+	/// This is useful for data which is generated while it is being sent, e.g.
+	/// the output of a process. HTTP/1.1 clients receive the data using
+	/// ``Transfer-Encoding: chunked``, so the connection can be kept alive.
+	/// HTTP/1.0 clients don't support this, so the end of the data is signaled
+	/// by closing the connection instead.
+	///
+	/// If the request was a HEAD request, ``data`` is not sent (or read).
+	///
+	/// # Parameters
+	/// * ``status_code``: Select the status code of the response, e.g. ``200 OK``.
+	/// * ``data``: Data to transmit. May be empty.
+	/// * ``headers``: Additional headers to add to the response. May be empty.
+	pub fn respond_stream(
+		&mut self,
+		status_code: &str,
+		data: impl Read,
+		headers: &[String]) -> io::Result<usize>
 	{
-		// Write status line
-		let mut bytes_written =
-			self.stream.write(format!("HTTP/1.0 {}\r\nContent-Length: {}\r\n", status_code, content_size).as_bytes())?;
+		self.send(legacy_response(status_code, headers)?.body_stream(data))
+	}
+
+	/// Send a response to the client. Returns the number of bytes written.
+	///
+	/// ``Content-Length`` or ``Transfer-Encoding`` are set depending on the
+	/// body, and ``Connection`` depending on whether the connection is kept
+	/// alive; any such headers in the response are replaced. A response with
+	/// ``Connection: close`` closes the connection. ``Date`` and ``Server``
+	/// are added unless present.
+	///
+	/// If the request was a HEAD request or the status does not allow a body
+	/// (e.g. ``304 Not Modified``), the body is not sent (or read).
+	///
+	/// If the client was passed on by an ``EventLoop``, a body set with
+	/// ``Response::body_file`` is sent by the event loop after the client is
+	/// dropped, and only the head is counted.
+	///
+	/// Fails with ``std::io::ErrorKind::InvalidInput`` if a header name is
+	/// not a valid token or a value contains a line break.
+	///
+	/// ```
+	/// use micro_http_server::{MicroHTTP, Response, StatusCode};
+	/// use std::io::*;
+	/// let server = MicroHTTP::new("127.0.0.1:4001").expect("Could not create server.");
+	/// # let mut connection = ::std::net::TcpStream::connect("127.0.0.1:4001").unwrap();
+	/// # connection.write("GET /\r\n\r\n".as_bytes());
+	/// let mut client = server.next_client().unwrap().unwrap();
+	/// client.next_request().unwrap();
+	///
+	/// client.send(Response::new(StatusCode::NotFound)
+	///     .header("Content-Type", "text/plain")
+	///     .body("Nothing here.".as_bytes())).unwrap();
+	/// ```
+	pub fn send(&mut self, response: Response<'_>) -> io::Result<usize> {
+		#[cfg(all(feature = "event-loop", unix))]
+		self.finish_deferred()?;
+		let (status, mut headers, body) = response.into_parts();
+
+		// These are determined by the body and the connection
+		headers.remove("content-length");
+		headers.remove("transfer-encoding");
+		if headers.contains_token("connection", "close") {
+			self.keep_alive = false;
+		}
+		headers.remove("connection");
+		if let Some((name, _)) = headers.iter().find(|(k, v)| !is_token(k) || v.contains(['\r', '\n'])) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput, format!("Invalid header: {}", name)));
+		}
+		if !headers.contains("date") {
+			headers.insert("Date", httpdate::fmt_http_date(SystemTime::now()));
+		}
+		if !headers.contains("server") {
+			headers.insert("Server", SERVER);
+		}
+
+		// The length of the body, if known in advance
+		let content_size = match &body {
+			ResponseBody::Empty => Some(0),
+			ResponseBody::Bytes(data) => Some(data.len()),
+			ResponseBody::Reader(_, length) => Some(*length),
+			ResponseBody::Stream(_) => None,
+			ResponseBody::File(_, length) => *length
+		};
+
+		let has_body = !self.is_head && !status.forbids_body();
+		let is_chunked = content_size.is_none() && self.version == Version::HTTP11;
+		let framing = if status.forbids_body() {
+			String::new()
+		} else if let Some(content_size) = content_size {
+			format!("Content-Length: {}", content_size)
+		} else if is_chunked {
+			"Transfer-Encoding: chunked".to_owned()
+		} else {
+			// Without a body, there is no need to signal where it ends
+			if has_body {
+				self.keep_alive = false;
+			}
+			String::new()
+		};
+
+		let head_size = self.write_head(status, &framing, &headers)?;
+		if !has_body {
+			self.reader.get_mut().flush()?;
+			return Ok(head_size);
+		}
+
+		let data: Box<dyn Read + '_> = match body {
+			ResponseBody::Empty => Box::new(io::empty()),
+			ResponseBody::Bytes(data) => Box::new(io::Cursor::new(data)),
+			ResponseBody::Reader(data, _) | ResponseBody::Stream(data) => data,
+			#[cfg(all(feature = "event-loop", unix))]
+			ResponseBody::File(data, _) if self.event_loop.is_some() => {
+				self.reader.get_mut().flush()?;
+				self.deferred = Some(DeferredBody::new(data, content_size, is_chunked));
+				return Ok(head_size);
+			},
+			ResponseBody::File(data, _) => data
+		};
+
+		let (data_size, body_size) = match content_size {
+			Some(content_size) => self.write_body(data.take(content_size as u64), false)?,
+			None => self.write_body(data, is_chunked)?
+		};
+		self.reader.get_mut().flush()?;
+
+		// If the body did not match the announced size, the client can't
+		// tell where the next response begins.
+		if content_size.map(|size| size != data_size).unwrap_or(false) {
+			self.keep_alive = false;
+		}
+
+		Ok(head_size + body_size)
+	}
+
+	/// Send a response without reading the request and close the connection,
+	/// e.g. to turn the client away because the server is busy. Returns the
+	/// number of bytes written.
+	///
+	/// The TLS handshake, which has to be finished first on a TLS connection,
+	/// may take up to ``header_read_timeout``. Afterwards, whatever the client
+	/// still sends is read and dropped for a moment, since closing the
+	/// connection with unread data resets it, which may make the client lose
+	/// the response.
+	pub fn reject(mut self, response: Response<'_>) -> io::Result<usize> {
+		self.keep_alive = false;
+		self.reader.get_ref().set_read_timeout(self.limits.header_read_timeout)?;
+		let size = self.send(response)?;
+		#[cfg(all(feature = "event-loop", unix))]
+		self.finish_deferred()?;
+		self.linger();
+		Ok(size)
+	}
+
+	// Stop sending and read what the client still sends, until it closes
+	// the connection or for up to LINGER_TIME
+	fn linger(&mut self) {
+		if self.reader.get_mut().shutdown_write().is_err() {
+			return;
+		}
+		let deadline = Instant::now() + LINGER_TIME;
+		let mut buffer = [0; Self::CHUNK_SIZE];
+		let mut size = 0;
+		while size < MAX_LINGER_SIZE {
+			let remaining = deadline.saturating_duration_since(Instant::now());
+			if remaining.is_zero() || self.reader.get_ref().set_read_timeout(Some(remaining)).is_err() {
+				break;
+			}
+			match self.reader.read(&mut buffer) {
+				Ok(0) | Err(_) => break,
+				Ok(bytes_read) => size += bytes_read
+			}
+		}
+	}
+
+	// Write the status line, the header which determines how the body is
+	// framed (if any), the "Connection" header and any additional headers.
+	fn write_head(&mut self, status: StatusCode, framing: &str, headers: &HeaderMap) -> io::Result<usize> {
+		self.has_responded = true;
+		// If the client hasn't been told to send the body it announced, it may
+		// or may not send it, so the next request can't be found. The same goes
+		// for a body which couldn't be read.
+		if self.body.prevents_reuse() {
+			self.keep_alive = false;
+		}
 
-		for h in headers {
-			bytes_written += self.stream.write(format!("{}\r\n", h).as_ref())?;
+		let mut head = format!("HTTP/1.1 {}\r\n", status);
+		if !framing.is_empty() {
+			head.push_str(framing);
+			head.push_str("\r\n");
 		}
-		bytes_written += self.stream.write("\r\n".as_bytes())?;
+		if !self.keep_alive {
+			head.push_str("Connection: close\r\n");
+		} else if self.version == Version::HTTP10 {
+			head.push_str("Connection: keep-alive\r\n");
+		}
+		head.push_str(&headers.to_string());
+		head.push_str("\r\n");
+		self.reader.get_mut().write_all(head.as_bytes())?;
+
+		Ok(head.len())
+	}
 
+	// Write the body, in chunks if is_chunked is set. Returns the size of the
+	// data and the number of bytes written including the chunk framing.
+	fn write_body(&mut self, mut data: impl Read, is_chunked: bool) -> io::Result<(usize, usize)> {
+		let mut data_size = 0;
+		let mut bytes_written = 0;
 		let mut buffer = [0; Self::CHUNK_SIZE];
 		loop {
-			let bytes_read = data.read(&mut buffer)?;
+			let bytes_read = match data.read(&mut buffer) {
+				Ok(bytes_read) => bytes_read,
+				Err(e) => {
+					// The response can't be completed, so the client has to
+					// notice that it is incomplete by the connection closing.
+					self.keep_alive = false;
+					return Err(e);
+				}
+			};
 			if bytes_read == 0 { break; }
-			bytes_written += self.stream.write(&buffer[..bytes_read])?;
+
+			if is_chunked {
+				// Write each chunk at once, so it isn't split into several
+				// packets (or TLS records)
+				let mut chunk = format!("{:X}\r\n", bytes_read).into_bytes();
+				chunk.extend_from_slice(&buffer[..bytes_read]);
+				chunk.extend_from_slice(b"\r\n");
+				self.reader.get_mut().write_all(&chunk)?;
+				bytes_written += chunk.len();
+			} else {
+				self.reader.get_mut().write_all(&buffer[..bytes_read])?;
+				bytes_written += bytes_read;
+			}
+			data_size += bytes_read;
 		}
 
-		Ok(bytes_written)
+		if is_chunked {
+			// Last chunk, without any trailers
+			self.reader.get_mut().write_all(b"0\r\n\r\n")?;
+			bytes_written += 5;
+		}
+
+		Ok((data_size, bytes_written))
 	}
 
 	const CHUNK_SIZE: usize = 4096;
 }
+
+// Used by the event loop, which holds on to clients between requests and
+// while it sends their responses.
+#[cfg(all(feature = "event-loop", unix))]
+impl Client {
+	pub(crate) fn socket(&self) -> Option<RawFd> {
+		self.reader.get_ref().socket()
+	}
+
+	pub(crate) fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		self.reader.get_ref().set_nonblocking(nonblocking)
+	}
+
+	// Whether a request may already have been received, without the socket
+	// becoming readable again
+	pub(crate) fn has_buffered_input(&mut self) -> bool {
+		!self.reader.buffer().is_empty() || self.reader.get_mut().has_buffered_input()
+	}
+
+	// Whether the connection is kept open after the last response, once its
+	// body has been sent
+	pub(crate) fn waits_for_request(&self) -> bool {
+		self.keep_alive
+	}
+
+	pub(crate) fn has_deferred_body(&self) -> bool {
+		self.deferred.is_some()
+	}
+
+	// Pass the client on for its next request. Until that request is read, the
+	// connection is closed when the client is dropped.
+	pub(crate) fn attach(&mut self, event_loop: LoopLink) {
+		self.request = None;
+		self.request_error = None;
+		self.keep_alive = false;
+		self.event_loop = Some(event_loop);
+	}
+
+	// Send as much of the deferred body as possible without blocking. Returns
+	// whether it has been sent completely.
+	pub(crate) fn write_deferred(&mut self) -> io::Result<bool> {
+		let deferred = match self.deferred.as_mut() {
+			Some(deferred) => deferred,
+			None => return Ok(true)
+		};
+		let stream = self.reader.get_mut();
+		loop {
+			if deferred.written < deferred.buffer.len() {
+				match stream.write(&deferred.buffer[deferred.written..]) {
+					Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+					Ok(bytes_written) => deferred.written += bytes_written,
+					Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+					Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+					Err(e) => return Err(e)
+				}
+			} else if deferred.is_done {
+				match stream.flush() {
+					Ok(()) => break,
+					Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+					Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+					Err(e) => return Err(e)
+				}
+			} else {
+				match deferred.fill() {
+					Ok(is_complete) => if !is_complete {
+						// The client can't tell where the next response begins
+						self.keep_alive = false;
+					},
+					Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+					Err(e) => {
+						self.keep_alive = false;
+						return Err(e);
+					}
+				}
+			}
+		}
+		self.deferred = None;
+		Ok(true)
+	}
+
+	// Send the deferred body, if any, before anything else is sent or read
+	fn finish_deferred(&mut self) -> io::Result<()> {
+		// The socket is blocking, unless it is being sent by the event loop,
+		// so not getting any further means the write timeout expired
+		if !self.write_deferred()? {
+			return Err(io::ErrorKind::TimedOut.into());
+		}
+		Ok(())
+	}
+}
+
+#[cfg(all(feature = "event-loop", unix))]
+impl Drop for Client {
+	fn drop(&mut self) {
+		if let Some(event_loop) = self.event_loop.take() {
+			// The rest of the request's body is skipped here, so the event
+			// loop only has to wait for the next request
+			if self.keep_alive && io::copy(&mut self.body(), &mut io::sink()).is_err() {
+				self.keep_alive = false;
+			}
+			let closed = Client::with_transport(Box::new(Untimed(io::empty())), self.addr.clone(), self.limits);
+			event_loop.give_back(mem::replace(self, closed));
+		}
+	}
+}
+
+// The body of a response which is sent by the event loop, see
+// ``Response::body_file``
+#[cfg(all(feature = "event-loop", unix))]
+struct DeferredBody {
+	data: Box<dyn Read + Send>,
+	// The number of bytes left to read, if the length was announced
+	remaining: Option<usize>,
+	is_chunked: bool,
+	// Data which was read, including the chunk framing, and how much of it
+	// was written so far
+	buffer: Vec<u8>,
+	written: usize,
+	is_done: bool
+}
+
+#[cfg(all(feature = "event-loop", unix))]
+impl DeferredBody {
+	fn new(data: Box<dyn Read + Send>, remaining: Option<usize>, is_chunked: bool) -> Self {
+		DeferredBody {
+			data,
+			remaining,
+			is_chunked,
+			buffer: Vec::new(),
+			written: 0,
+			is_done: false
+		}
+	}
+
+	// Read the next part of the body into the buffer. Returns false if the
+	// data ended before the announced length.
+	fn fill(&mut self) -> io::Result<bool> {
+		let max_len = self.remaining.unwrap_or(Client::CHUNK_SIZE).min(Client::CHUNK_SIZE);
+		let mut data = vec![0; max_len];
+		let bytes_read = if max_len == 0 { 0 } else { self.data.read(&mut data)? };
+		data.truncate(bytes_read);
+
+		self.written = 0;
+		self.is_done = bytes_read == 0;
+		self.buffer = if !self.is_chunked {
+			data
+		} else if bytes_read > 0 {
+			let mut chunk = format!("{:X}\r\n", bytes_read).into_bytes();
+			chunk.extend_from_slice(&data);
+			chunk.extend_from_slice(b"\r\n");
+			chunk
+		} else {
+			// Last chunk, without any trailers
+			b"0\r\n\r\n".to_vec()
+		};
+
+		match &mut self.remaining {
+			Some(remaining) => {
+				*remaining -= bytes_read;
+				Ok(bytes_read > 0 || *remaining == 0)
+			},
+			None => Ok(true)
+		}
+	}
+}
+
+#[cfg(all(feature = "event-loop", unix))]
+impl fmt::Debug for DeferredBody {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("DeferredBody")
+			.field("remaining", &self.remaining)
+			.field("is_chunked", &self.is_chunked)
+			.finish()
+	}
+}
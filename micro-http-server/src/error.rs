@@ -0,0 +1,101 @@
+use std::{fmt, io};
+
+use crate::client::URL;
+use crate::status::StatusCode;
+
+/// An error in a request made by a client. Each variant corresponds to the
+/// status code the server should respond with, after which the connection
+/// should be closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+	/// The request line or headers are malformed, answer with
+	/// ``400 Bad Request``
+	BadRequest,
+	/// The method is a standard HTTP method which µHTTP does not support,
+	/// e.g. ``PUT`` or ``DELETE``. Answer with ``405 Method Not Allowed``
+	/// and an ``Allow`` header listing the methods usable on the URL.
+	MethodNotAllowed(String, URL),
+	/// The method is not known at all, answer with ``501 Not Implemented``
+	NotImplemented(String),
+	/// The client took too long to send the request, answer with
+	/// ``408 Request Timeout``
+	RequestTimeout,
+	/// The request body is larger than allowed, answer with
+	/// ``413 Payload Too Large``
+	PayloadTooLarge,
+	/// The request line is longer than allowed, answer with
+	/// ``414 URI Too Long``
+	URITooLong,
+	/// There are too many headers or they are larger than allowed, answer
+	/// with ``431 Request Header Fields Too Large``
+	RequestHeaderFieldsTooLarge,
+	/// The request is made with a major version of HTTP other than 1, e.g.
+	/// ``HTTP/2.0``, answer with ``505 HTTP Version Not Supported``
+	HTTPVersionNotSupported
+}
+
+impl RequestError {
+	/// Return the ``RequestError`` wrapped in an ``std::io::Error``, if there is
+	/// one. This is the case for errors returned while reading a ``Body``.
+	pub fn from_io_error(e: &io::Error) -> Option<&RequestError> {
+		e.get_ref().and_then(|e| e.downcast_ref())
+	}
+
+	/// Return the status to respond to the request with, e.g.
+	/// ``StatusCode::BadRequest``.
+	pub fn status(&self) -> StatusCode {
+		match self {
+			RequestError::BadRequest => StatusCode::BadRequest,
+			RequestError::MethodNotAllowed(_, _) => StatusCode::MethodNotAllowed,
+			RequestError::NotImplemented(_) => StatusCode::NotImplemented,
+			RequestError::RequestTimeout => StatusCode::RequestTimeout,
+			RequestError::PayloadTooLarge => StatusCode::PayloadTooLarge,
+			RequestError::URITooLong => StatusCode::URITooLong,
+			RequestError::RequestHeaderFieldsTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+			RequestError::HTTPVersionNotSupported => StatusCode::HTTPVersionNotSupported
+		}
+	}
+}
+
+impl fmt::Display for RequestError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RequestError::BadRequest => write!(f, "Malformed request"),
+			RequestError::MethodNotAllowed(method, url) => write!(f, "Method {} not allowed for {}", method, url),
+			RequestError::NotImplemented(method) => write!(f, "Unknown method {}", method),
+			RequestError::RequestTimeout => write!(f, "Timed out reading request"),
+			RequestError::PayloadTooLarge => write!(f, "Request body too large"),
+			RequestError::URITooLong => write!(f, "Request line too long"),
+			RequestError::RequestHeaderFieldsTooLarge => write!(f, "Request headers too large"),
+			RequestError::HTTPVersionNotSupported => write!(f, "Unsupported HTTP version")
+		}
+	}
+}
+
+impl std::error::Error for RequestError {}
+
+impl From<RequestError> for io::Error {
+	fn from(e: RequestError) -> Self {
+		io::Error::new(io::ErrorKind::InvalidData, e)
+	}
+}
+
+// An error while reading a request, which is either caused by the connection
+// or by the contents of the request.
+#[derive(Debug)]
+pub(crate) enum ReadError {
+	Io(io::Error),
+	Request(RequestError)
+}
+
+impl From<io::Error> for ReadError {
+	fn from(e: io::Error) -> Self {
+		ReadError::Io(e)
+	}
+}
+
+impl From<RequestError> for ReadError {
+	fn from(e: RequestError) -> Self {
+		ReadError::Request(e)
+	}
+}
@@ -0,0 +1,351 @@
+use std::{
+	io,
+	mem,
+	os::unix::io::AsRawFd,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant}
+};
+use mio::{Events, Interest, Poll, Registry, Token, Waker, unix::SourceFd};
+use crate::{client::Client, listener::Listener, microhttp::MicroHTTP};
+
+// The token of the waker, which wakes the event loop when a client is given back
+const WAKER: Token = Token(usize::MAX);
+// The listeners get the tokens below the waker's, followed by the sockets
+// which become readable when a server is stopped. Connections get the
+// lowest tokens, which are their slots.
+const FIRST_SERVER_TOKEN: usize = usize::MAX - 1;
+
+// How often connections are checked for timeouts
+const TIMEOUT_INTERVAL: Duration = Duration::from_secs(1);
+
+// The number of events handled at once
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Serves the connections of one or more servers on a single thread, so that
+/// connections which are kept alive between requests, and responses with a
+/// body set by ``Response::body_file``, don't need a thread each. Requires
+/// the ``event-loop`` feature and a Unix-like system.
+///
+/// Whenever a client starts sending a request, it is passed to a function
+/// which hands it to another thread (e.g. of a pool), where the request is read
+/// with ``Client::next_request`` and answered as usual. Handlers may block,
+/// e.g. to run a process. Instead of closing the connection, dropping the
+/// client gives it back to the event loop, which sends the rest of a
+/// ``body_file`` body and waits for the next request. Clients which are kept
+/// idle for longer than their ``Limits::idle_timeout``, or don't receive any
+/// data for longer than their ``Limits::write_timeout``, are closed.
+///
+/// The event loop runs until all servers are stopped (see
+/// ``MicroHTTP::stop_handle``). Connections which are idle at that point are
+/// closed; the others are served until their current response is complete.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::{fs::File, thread};
+/// use micro_http_server::{EventLoop, MicroHTTP, Response};
+///
+/// let server = MicroHTTP::new("127.0.0.1:3000").expect("Could not create server.");
+/// EventLoop::new().unwrap().run(&[&server], |mut client| {
+///     thread::spawn(move || {
+///         if let Ok(true) = client.next_request() {
+///             let file = File::open("/srv/cat.txt").unwrap();
+///             let length = file.metadata().unwrap().len() as usize;
+///             client.send(Response::ok().body_file(file, Some(length))).unwrap();
+///         }
+///         // The client is given back to the event loop here
+///     });
+/// }).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct EventLoop {
+	poll: Poll,
+	shared: Arc<Shared>
+}
+
+// The part of the event loop which clients are given back to
+#[derive(Debug)]
+struct Shared {
+	waker: Waker,
+	// The clients which were given back, with their slots
+	returned: Mutex<Vec<(usize, Client)>>
+}
+
+// Ties a client which was passed on to the event loop it came from
+#[derive(Debug)]
+pub(crate) struct LoopLink {
+	shared: Arc<Shared>,
+	slot: usize
+}
+
+impl LoopLink {
+	pub(crate) fn give_back(self, client: Client) {
+		self.shared.returned.lock().unwrap_or_else(|e| e.into_inner()).push((self.slot, client));
+		// If the event loop is gone, the client is closed along with the
+		// shared part
+		drop(self.shared.waker.wake());
+	}
+}
+
+// The state of a connection
+enum Slot {
+	// The client was passed on and hasn't been given back yet
+	Dispatched,
+	// Waiting for the next request, since the given time
+	Idle(Client, Instant),
+	// Sending the body of a response, which last made progress at the given time
+	Sending(Client, Instant)
+}
+
+impl EventLoop {
+	/// Create an event loop.
+	pub fn new() -> io::Result<EventLoop> {
+		let poll = Poll::new()?;
+		let waker = Waker::new(poll.registry(), WAKER)?;
+		Ok(EventLoop {
+			poll,
+			shared: Arc::new(Shared { waker, returned: Mutex::new(Vec::new()) })
+		})
+	}
+
+	/// Accept connections on the listeners of ``servers`` and pass each
+	/// client to ``dispatch`` once it starts sending a request, until the
+	/// servers are stopped and all clients are done.
+	///
+	/// Fails if waiting for events fails, which doesn't happen normally.
+	pub fn run<F>(mut self, servers: &[&MicroHTTP], dispatch: F) -> io::Result<()>
+	where F: FnMut(Client)
+	{
+		let listeners: Vec<(&MicroHTTP, &Listener)> = servers.iter()
+			.flat_map(|server| server.listeners().iter().map(move |listener| (*server, listener)))
+			.collect();
+		let last_server_token = FIRST_SERVER_TOKEN - listeners.len() - servers.len();
+		let registry = self.poll.registry().try_clone()?;
+		for (i, (_, listener)) in listeners.iter().enumerate() {
+			registry.register(&mut SourceFd(&listener.as_raw_fd()), Token(FIRST_SERVER_TOKEN - i), Interest::READABLE)?;
+		}
+		for (i, server) in servers.iter().enumerate() {
+			let wake_fd = server.stop_state().wake_fd();
+			registry.register(&mut SourceFd(&wake_fd), Token(FIRST_SERVER_TOKEN - listeners.len() - i), Interest::READABLE)?;
+		}
+
+		let mut connections = Connections {
+			registry,
+			shared: self.shared.clone(),
+			slots: Vec::new(),
+			free: Vec::new(),
+			dispatch: Some(dispatch)
+		};
+		let mut events = Events::with_capacity(EVENTS_CAPACITY);
+		let mut next_check = Instant::now() + TIMEOUT_INTERVAL;
+		loop {
+			if connections.dispatch.is_some() && servers.iter().all(|server| server.is_stopped()) {
+				connections.stop();
+			}
+			if connections.dispatch.is_none() && connections.is_empty() {
+				return Ok(());
+			}
+
+			// Without connections, there are no timeouts to check
+			let timeout = if connections.is_empty() {
+				None
+			} else {
+				Some(next_check.saturating_duration_since(Instant::now()))
+			};
+			if let Err(e) = self.poll.poll(&mut events, timeout) {
+				if e.kind() == io::ErrorKind::Interrupted {
+					continue;
+				}
+				return Err(e);
+			}
+
+			for event in events.iter() {
+				match event.token() {
+					WAKER => connections.take_returned(),
+					Token(token) if token > last_server_token => {
+						// Stopped servers are noticed above
+						if let Some((server, listener)) = listeners.get(FIRST_SERVER_TOKEN - token) {
+							if !server.is_stopped() {
+								connections.accept(server, listener);
+							}
+						}
+					},
+					Token(slot) => connections.ready(slot)
+				}
+			}
+
+			let now = Instant::now();
+			if now >= next_check {
+				connections.close_timed_out(now);
+				next_check = now + TIMEOUT_INTERVAL;
+			}
+		}
+	}
+}
+
+// The connections of an event loop, indexed by their slots
+struct Connections<F> {
+	registry: Registry,
+	shared: Arc<Shared>,
+	slots: Vec<Option<Slot>>,
+	// Slots which may be reused
+	free: Vec<usize>,
+	// Dropped once the servers are stopped
+	dispatch: Option<F>
+}
+
+impl<F: FnMut(Client)> Connections<F> {
+	fn is_empty(&self) -> bool {
+		self.free.len() == self.slots.len()
+	}
+
+	// Accept all connections which are waiting, since there is only an event
+	// when new ones arrive
+	fn accept(&mut self, server: &MicroHTTP, listener: &Listener) {
+		loop {
+			let (socket, addr) = match listener.accept() {
+				Ok(connection) => connection,
+				Err(e) if matches!(e.kind(), io::ErrorKind::ConnectionAborted | io::ErrorKind::Interrupted) => continue,
+				// Other errors may persist (e.g. running out of file
+				// descriptors), so wait for the next connection
+				Err(_) => break
+			};
+			if let Ok(client) = server.client_for(socket, addr) {
+				let slot = match self.free.pop() {
+					Some(slot) => slot,
+					None => {
+						self.slots.push(None);
+						self.slots.len() - 1
+					}
+				};
+				self.wait_for_request(slot, client);
+			}
+		}
+	}
+
+	// Handle an event for the connection in a slot
+	fn ready(&mut self, slot: usize) {
+		match self.slots.get_mut(slot).and_then(Option::take) {
+			Some(Slot::Idle(client, _)) => {
+				self.deregister(&client);
+				self.pass_on(slot, client);
+			},
+			Some(Slot::Sending(mut client, _)) => match client.write_deferred() {
+				Ok(true) => {
+					self.deregister(&client);
+					self.resume(slot, client);
+				},
+				Ok(false) => self.slots[slot] = Some(Slot::Sending(client, Instant::now())),
+				Err(_) => {
+					self.deregister(&client);
+					self.close(slot);
+				}
+			},
+			// The event was meant for a connection which is gone
+			state => {
+				if let Some(entry) = self.slots.get_mut(slot) {
+					*entry = state;
+				}
+			}
+		}
+	}
+
+	// Continue with a client which was given back
+	fn take_returned(&mut self) {
+		let returned = mem::take(&mut *self.shared.returned.lock().unwrap_or_else(|e| e.into_inner()));
+		for (slot, client) in returned {
+			self.resume(slot, client);
+		}
+	}
+
+	// Send the rest of the client's response, or wait for its next request
+	// if the connection is kept alive
+	fn resume(&mut self, slot: usize, client: Client) {
+		if client.has_deferred_body() {
+			let sending = client.set_nonblocking(true)
+				.and_then(|()| self.register(&client, slot, Interest::WRITABLE))
+				.map(|()| Slot::Sending(client, Instant::now()));
+			match sending {
+				Ok(sending) => self.slots[slot] = Some(sending),
+				Err(_) => self.close(slot)
+			}
+		} else if client.waits_for_request() {
+			self.wait_for_request(slot, client);
+		} else {
+			self.close(slot);
+		}
+	}
+
+	fn wait_for_request(&mut self, slot: usize, mut client: Client) {
+		if self.dispatch.is_none() {
+			self.close(slot);
+		} else if client.has_buffered_input() {
+			// The next request was sent along with the previous one
+			self.pass_on(slot, client);
+		} else {
+			match self.register(&client, slot, Interest::READABLE) {
+				Ok(()) => self.slots[slot] = Some(Slot::Idle(client, Instant::now())),
+				Err(_) => self.close(slot)
+			}
+		}
+	}
+
+	// Pass the client on to read and answer its request
+	fn pass_on(&mut self, slot: usize, mut client: Client) {
+		if client.set_nonblocking(false).is_err() {
+			return self.close(slot);
+		}
+		match &mut self.dispatch {
+			Some(dispatch) => {
+				client.attach(LoopLink { shared: self.shared.clone(), slot });
+				self.slots[slot] = Some(Slot::Dispatched);
+				dispatch(client);
+			},
+			None => self.close(slot)
+		}
+	}
+
+	// Close the idle connections and stop passing clients on
+	fn stop(&mut self) {
+		self.dispatch = None;
+		for slot in 0..self.slots.len() {
+			if let Some(Slot::Idle(client, _)) = &self.slots[slot] {
+				self.deregister(client);
+				self.close(slot);
+			}
+		}
+	}
+
+	fn close_timed_out(&mut self, now: Instant) {
+		for slot in 0..self.slots.len() {
+			let (client, timeout, since) = match &self.slots[slot] {
+				Some(Slot::Idle(client, since)) => (client, client.limits().idle_timeout, since),
+				Some(Slot::Sending(client, since)) => (client, client.limits().write_timeout, since),
+				_ => continue
+			};
+			if timeout.is_some_and(|timeout| now.saturating_duration_since(*since) >= timeout) {
+				self.deregister(client);
+				self.close(slot);
+			}
+		}
+	}
+
+	// Free the slot, which closes the connection in it, if any
+	fn close(&mut self, slot: usize) {
+		self.slots[slot] = None;
+		self.free.push(slot);
+	}
+
+	fn register(&self, client: &Client, slot: usize, interest: Interest) -> io::Result<()> {
+		let fd = client.socket().ok_or(io::ErrorKind::Unsupported)?;
+		self.registry.register(&mut SourceFd(&fd), Token(slot), interest)
+	}
+
+	// Stop waiting for the client's socket, e.g. before it is passed on.
+	// Closed sockets are forgotten by themselves.
+	fn deregister(&self, client: &Client) {
+		if let Some(fd) = client.socket() {
+			drop(self.registry.deregister(&mut SourceFd(&fd)));
+		}
+	}
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+
+/// A list of header fields whose names are compared case-insensitively, used
+/// for both requests (see ``Headers``) and responses.
+///
+/// Names keep the case they were inserted with, and fields keep their order.
+/// A name may occur several times, e.g. for ``Cookie`` or ``Set-Cookie``.
+///
+/// # Example
+///
+/// ```
+/// use micro_http_server::HeaderMap;
+///
+/// let mut headers = HeaderMap::new();
+/// headers.insert("Cache-Control", "no-cache");
+/// headers.insert("cache-control", "max-age=30");
+/// headers.append("Set-Cookie", "a=1");
+/// headers.append("Set-Cookie", "b=2");
+///
+/// assert_eq!(Some("max-age=30"), headers.get("CACHE-CONTROL"));
+/// assert_eq!(vec!["a=1", "b=2"], headers.get_all("set-cookie").collect::<Vec<_>>());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+	fields: Vec<(String, String)>
+}
+
+impl HeaderMap {
+	/// Create an empty header map.
+	pub fn new() -> Self {
+		HeaderMap::default()
+	}
+
+	/// Return the value of the first field with the given name.
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.fields.iter()
+			.find(|(k, _)| k.eq_ignore_ascii_case(name))
+			.map(|(_, v)| v.as_str())
+	}
+
+	/// Return the values of all fields with the given name, in order.
+	pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+		self.fields.iter()
+			.filter(move |(k, _)| k.eq_ignore_ascii_case(name))
+			.map(|(_, v)| v.as_str())
+	}
+
+	/// Return the elements of all fields with the given name whose values are
+	/// comma-separated lists, like ``Accept`` or ``X-Forwarded-For``, in order.
+	/// Whitespace around elements and empty elements are skipped, and commas in
+	/// quoted strings don't separate elements.
+	///
+	/// ```
+	/// use micro_http_server::HeaderMap;
+	///
+	/// let mut headers = HeaderMap::new();
+	/// headers.append("Accept", "text/html, text/plain;q=0.5");
+	/// headers.append("Accept", "image/*;x=\"a,b\"");
+	///
+	/// assert_eq!(
+	///     vec!["text/html", "text/plain;q=0.5", "image/*;x=\"a,b\""],
+	///     headers.get_list("accept").collect::<Vec<_>>());
+	/// ```
+	pub fn get_list<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+		self.get_all(name).flat_map(split_list)
+	}
+
+	/// Whether the list held by the fields with the given name (see
+	/// ``get_list``) contains ``token``, ignoring case. This is useful for
+	/// headers like ``Connection: keep-alive, Upgrade``.
+	pub fn contains_token(&self, name: &str, token: &str) -> bool {
+		self.get_list(name).any(|t| t.eq_ignore_ascii_case(token))
+	}
+
+	/// Whether there is a field with the given name.
+	pub fn contains(&self, name: &str) -> bool {
+		self.get(name).is_some()
+	}
+
+	/// Set the value of a field, replacing any fields with the same name.
+	pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+		let name = name.into();
+		// The field takes the place of the first one it replaces
+		match self.fields.iter().position(|(k, _)| k.eq_ignore_ascii_case(&name)) {
+			Some(i) => {
+				let mut index = 0;
+				self.fields.retain(|(k, _)| {
+					index += 1;
+					index <= i + 1 || !k.eq_ignore_ascii_case(&name)
+				});
+				self.fields[i] = (name, value.into());
+			},
+			None => self.fields.push((name, value.into()))
+		}
+	}
+
+	/// Add a field, keeping any fields with the same name.
+	pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+		self.fields.push((name.into(), value.into()));
+	}
+
+	/// Remove all fields with the given name, returning the value of the first one.
+	pub fn remove(&mut self, name: &str) -> Option<String> {
+		let value = self.get(name).map(|v| v.to_owned());
+		self.fields.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+		value
+	}
+
+	/// Return all fields in order.
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+		self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+	}
+
+	/// Return the number of fields.
+	pub fn len(&self) -> usize {
+		self.fields.len()
+	}
+
+	/// Whether there are no fields.
+	pub fn is_empty(&self) -> bool {
+		self.fields.is_empty()
+	}
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for HeaderMap {
+	fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+		let mut headers = HeaderMap::new();
+		for (k, v) in iter {
+			headers.append(k, v);
+		}
+		headers
+	}
+}
+
+impl<K: Into<String>, V: Into<String>> Extend<(K, V)> for HeaderMap {
+	fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+		for (k, v) in iter {
+			self.append(k, v);
+		}
+	}
+}
+
+/// Formats the fields as they appear in a message, one ``Name: value`` line each.
+impl fmt::Display for HeaderMap {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (k, v) in &self.fields {
+			write!(f, "{}: {}\r\n", k, v)?;
+		}
+		Ok(())
+	}
+}
+
+// Split a comma-separated list, ignoring commas in quoted strings
+fn split_list(value: &str) -> Vec<&str> {
+	let mut elements = Vec::new();
+	let mut start = 0;
+	let mut in_quotes = false;
+	let mut is_escaped = false;
+	for (i, c) in value.char_indices() {
+		match c {
+			_ if is_escaped => is_escaped = false,
+			'\\' if in_quotes => is_escaped = true,
+			'"' => in_quotes = !in_quotes,
+			',' if !in_quotes => {
+				elements.push(&value[start..i]);
+				start = i + 1;
+			},
+			_ => {}
+		}
+	}
+	elements.push(&value[start..]);
+
+	elements.into_iter()
+		.map(|e| e.trim())
+		.filter(|e| !e.is_empty())
+		.collect()
+}
@@ -32,7 +32,8 @@
 //! {
 //! 	// Server side: Get client and send a response.
 //!     let mut client = server.next_client().unwrap().unwrap();
-//!     println!("[Server] Client requested: {}", client.request().as_ref().unwrap());
+//!     client.next_request().unwrap();
+//!     println!("[Server] Client requested: {}", client.request().as_ref().unwrap().path());
 //!     let bytes_written = client.respond_ok("Cats are nice.\n".as_bytes()).unwrap();
 //!     println!("[Server] Sent {} bytes to the client.", bytes_written);
 //! } // client is dropped here to close the TcpStream.
@@ -47,9 +48,44 @@
 
 mod microhttp;
 mod client;
+mod body;
+mod error;
+mod limits;
+mod media_type;
+mod multipart;
+mod header_map;
+mod response;
+mod status;
+mod query;
+mod transport;
+mod listener;
+mod memory;
+mod stop;
+mod proxy;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(all(feature = "event-loop", unix))]
+mod event_loop;
 
 pub use microhttp::MicroHTTP;
-pub use client::{Client, Request, QueryData, FormData, URL, Headers};
+pub use client::{Client, Request, Method, Version, FormData, URL, Headers};
+pub use body::Body;
+pub use error::RequestError;
+pub use limits::Limits;
+pub use media_type::MediaType;
+pub use multipart::{Multipart, Part};
+pub use header_map::HeaderMap;
+pub use response::{Response, ResponseBody};
+pub use status::StatusCode;
+pub use query::QueryData;
+pub use listener::{Listener, ClientAddr};
+pub use memory::ResponseCapture;
+pub use stop::StopHandle;
+pub use proxy::TrustedProxies;
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+#[cfg(all(feature = "event-loop", unix))]
+pub use event_loop::EventLoop;
 
 // #[cfg(not(target_os="windows"))]
 // fn os_windows() -> bool { false }
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+/// Limits on how long a client may take and how much data it may send, to
+/// prevent a single client from holding on to a connection (and the thread
+/// serving it) forever or from making the server use up its memory.
+///
+/// Requests exceeding a limit are reported through ``Client::request_error``.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use micro_http_server::{MicroHTTP, Limits};
+///
+/// let mut server = MicroHTTP::new("127.0.0.1:3001").expect("Could not create server.");
+/// server.set_limits(Limits {
+///     idle_timeout: Some(Duration::from_secs(5)),
+///     max_body_size: 1024,
+///     ..Limits::default()
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+	/// How long to wait for the client to start sending a request, and for
+	/// each piece of a request body. The connection is closed (without a
+	/// response) if this runs out. ``None`` waits forever.
+	pub idle_timeout: Option<Duration>,
+	/// How long the client may take to send the request line and headers once
+	/// it has started sending a request, answered with ``408 Request Timeout``.
+	/// ``None`` waits forever.
+	pub header_read_timeout: Option<Duration>,
+	/// How long to wait for the client to receive each piece of a response.
+	/// ``None`` waits forever.
+	pub write_timeout: Option<Duration>,
+	/// The maximum length of the request line in bytes, answered with
+	/// ``414 URI Too Long``
+	pub max_request_line: usize,
+	/// The maximum number of headers, answered with
+	/// ``431 Request Header Fields Too Large``
+	pub max_headers: usize,
+	/// The maximum size of all headers in bytes, answered with
+	/// ``431 Request Header Fields Too Large``
+	pub max_header_size: usize,
+	/// The maximum size of a request body in bytes, answered with
+	/// ``413 Payload Too Large``
+	pub max_body_size: usize
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Limits {
+			idle_timeout: Some(Duration::from_secs(15)),
+			header_read_timeout: Some(Duration::from_secs(30)),
+			write_timeout: Some(Duration::from_secs(60)),
+			max_request_line: 8 * 1024,
+			max_headers: 100,
+			max_header_size: 64 * 1024,
+			max_body_size: 64 * 1024 * 1024
+		}
+	}
+}
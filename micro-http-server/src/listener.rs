@@ -0,0 +1,276 @@
+use std::{
+	fmt,
+	io,
+	net::{SocketAddr, TcpListener}
+};
+use socket2::{Domain, Protocol, Socket, Type};
+#[cfg(unix)]
+use std::{
+	env,
+	fs,
+	os::unix::{
+		fs::FileTypeExt,
+		io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
+		net::UnixListener
+	},
+	path::{Path, PathBuf},
+	process
+};
+use crate::transport::Transport;
+use crate::stop::StopState;
+
+// The first file descriptor passed by systemd socket activation
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// A socket on which a server accepts connections, see ``MicroHTTP::from_listener``.
+#[derive(Debug)]
+pub enum Listener {
+	/// A TCP socket
+	Tcp(TcpListener),
+	/// A Unix domain socket
+	#[cfg(unix)]
+	Unix(UnixListener)
+}
+
+impl Listener {
+	/// Bind a TCP socket to the given address.
+	///
+	/// For IPv6 addresses, ``ipv6_only`` controls whether the socket only
+	/// accepts IPv6 connections. If it is ``false``, a socket on ``[::]``
+	/// accepts IPv4 connections as well (their addresses appear as
+	/// IPv4-mapped IPv6 addresses, like ``::ffff:1.2.3.4``), so a separate
+	/// socket on ``0.0.0.0`` isn't needed. It has no effect for IPv4 addresses.
+	///
+	/// # Example
+	///
+	/// ```no_run
+	/// use micro_http_server::{Listener, MicroHTTP};
+	///
+	/// // Accept both IPv4 and IPv6 connections on port 8080
+	/// let listener = Listener::bind_tcp("[::]:8080".parse().unwrap(), false)
+	///     .expect("Could not bind socket.");
+	/// let server = MicroHTTP::from_listener(listener);
+	/// ```
+	pub fn bind_tcp(addr: SocketAddr, ipv6_only: bool) -> io::Result<Listener> {
+		let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+		if addr.is_ipv6() {
+			socket.set_only_v6(ipv6_only)?;
+		}
+		// Like std, allow binding again right after a previous server exited
+		#[cfg(unix)]
+		socket.set_reuse_address(true)?;
+		socket.bind(&addr.into())?;
+		socket.listen(128)?;
+		Ok(Listener::Tcp(socket.into()))
+	}
+
+	/// Bind a Unix domain socket at the given path. If there is a socket at
+	/// the path already, e.g. left over from a previous run, it is replaced;
+	/// any other kind of file is left alone and binding fails.
+	///
+	/// The socket is created with the permissions allowed by the process's
+	/// umask; use ``std::fs::set_permissions`` to change them.
+	#[cfg(unix)]
+	pub fn bind_unix(path: impl AsRef<Path>) -> io::Result<Listener> {
+		let path = path.as_ref();
+		match fs::symlink_metadata(path) {
+			Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+			_ => {}
+		}
+		Ok(Listener::Unix(UnixListener::bind(path)?))
+	}
+
+	/// Return the listening sockets passed to this process by systemd socket
+	/// activation (see ``sd_listen_fds(3)``), in the order they are configured
+	/// in the socket unit. If the process wasn't started this way, the list
+	/// is empty.
+	///
+	/// Only TCP and Unix domain stream sockets are supported. The sockets are
+	/// not inherited by child processes.
+	#[cfg(unix)]
+	pub fn from_systemd() -> io::Result<Vec<Listener>> {
+		// The variables may have been inherited from a parent process which
+		// was started by systemd, in which case they are not meant for us
+		let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+		if pid != Some(process::id()) {
+			return Ok(Vec::new());
+		}
+
+		let count: RawFd = env::var("LISTEN_FDS").ok()
+			.and_then(|count| count.parse().ok())
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid LISTEN_FDS"))?;
+		(SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+			.map(|fd| unsafe { Listener::from_inherited(fd) })
+			.collect()
+	}
+
+	/// Take ownership of a listening socket this process inherited from its
+	/// parent, e.g. when a server hands its sockets over to a new version of
+	/// itself. TCP and Unix domain sockets are told apart by their address.
+	///
+	/// The socket is not inherited by child processes of this one.
+	///
+	/// # Safety
+	///
+	/// ``fd`` must be an open listening stream socket which nothing else in
+	/// this process uses or closes.
+	#[cfg(unix)]
+	pub unsafe fn from_inherited(fd: RawFd) -> io::Result<Listener> {
+		// Unlike the sockets created by std, inherited ones may be passed on to
+		// child processes
+		if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1 {
+			return Err(io::Error::last_os_error());
+		}
+
+		let listener = TcpListener::from_raw_fd(fd);
+		if listener.local_addr().is_ok() {
+			return Ok(Listener::Tcp(listener));
+		}
+		let listener = UnixListener::from_raw_fd(listener.into_raw_fd());
+		listener.local_addr()?;
+		Ok(Listener::Unix(listener))
+	}
+
+	pub(crate) fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		match self {
+			Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+			#[cfg(unix)]
+			Listener::Unix(listener) => listener.set_nonblocking(nonblocking)
+		}
+	}
+
+	// Accept a connection. Connections are always blocking, even if the
+	// listener is not (on some systems, they would inherit its mode).
+	pub(crate) fn accept(&self) -> io::Result<(Box<dyn Transport>, ClientAddr)> {
+		match self {
+			Listener::Tcp(listener) => {
+				let (stream, addr) = listener.accept()?;
+				stream.set_nonblocking(false)?;
+				Ok((Box::new(stream), ClientAddr::Inet(addr)))
+			},
+			#[cfg(unix)]
+			Listener::Unix(listener) => {
+				let (stream, addr) = listener.accept()?;
+				stream.set_nonblocking(false)?;
+				Ok((Box::new(stream), ClientAddr::Unix(addr.as_pathname().map(Path::to_owned))))
+			}
+		}
+	}
+}
+
+#[cfg(unix)]
+impl AsRawFd for Listener {
+	fn as_raw_fd(&self) -> RawFd {
+		match self {
+			Listener::Tcp(listener) => listener.as_raw_fd(),
+			Listener::Unix(listener) => listener.as_raw_fd()
+		}
+	}
+}
+
+// Accept a connection from whichever of the listeners, which must all be
+// nonblocking, gets one first. Unless ``nonblocking`` is set, this waits
+// until there is a connection or the server is stopped.
+#[cfg(unix)]
+pub(crate) fn accept_any(listeners: &[Listener], nonblocking: bool, stop: &StopState) -> io::Result<(Box<dyn Transport>, ClientAddr)> {
+	let mut fds: Vec<libc::pollfd> = listeners.iter()
+		.map(|listener| listener.as_raw_fd())
+		.chain(Some(stop.wake_fd()))
+		.map(|fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 })
+		.collect();
+	let timeout = if nonblocking { 0 } else { -1 };
+
+	loop {
+		match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } {
+			-1 => {
+				let e = io::Error::last_os_error();
+				if e.kind() != io::ErrorKind::Interrupted {
+					return Err(e);
+				}
+			},
+			0 => return Err(io::ErrorKind::WouldBlock.into()),
+			_ if stop.is_stopped() => return Err(io::ErrorKind::WouldBlock.into()),
+			_ => {
+				for (listener, fd) in listeners.iter().zip(&mut fds) {
+					if fd.revents == 0 {
+						continue;
+					}
+					fd.revents = 0;
+					match listener.accept() {
+						// The client may have gone away in the meantime
+						Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+						result => return result
+					}
+				}
+				if nonblocking {
+					return Err(io::ErrorKind::WouldBlock.into());
+				}
+			}
+		}
+	}
+}
+
+// Without poll, check the listeners in turn
+#[cfg(not(unix))]
+pub(crate) fn accept_any(listeners: &[Listener], nonblocking: bool, stop: &StopState) -> io::Result<(Box<dyn Transport>, ClientAddr)> {
+	while !stop.is_stopped() {
+		for listener in listeners {
+			match listener.accept() {
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+				result => return result
+			}
+		}
+		if nonblocking {
+			return Err(io::ErrorKind::WouldBlock.into());
+		}
+		std::thread::sleep(std::time::Duration::from_millis(10));
+	}
+	Err(io::ErrorKind::WouldBlock.into())
+}
+
+impl From<TcpListener> for Listener {
+	fn from(listener: TcpListener) -> Self {
+		Listener::Tcp(listener)
+	}
+}
+
+#[cfg(unix)]
+impl From<UnixListener> for Listener {
+	fn from(listener: UnixListener) -> Self {
+		Listener::Unix(listener)
+	}
+}
+
+/// The address of a client.
+///
+/// Formatting the address with ``Display`` gives e.g. ``1.2.3.4:9435`` for
+/// TCP clients.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientAddr {
+	/// The address of a client connected over TCP
+	Inet(SocketAddr),
+	/// A client connected over a Unix domain socket, with the path of its
+	/// socket if it has one. Clients usually don't bind their socket to a
+	/// path, so this is mostly ``None``.
+	#[cfg(unix)]
+	Unix(Option<PathBuf>)
+}
+
+impl fmt::Display for ClientAddr {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ClientAddr::Inet(addr) => write!(f, "{}", addr),
+			#[cfg(unix)]
+			ClientAddr::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+			#[cfg(unix)]
+			ClientAddr::Unix(None) => write!(f, "unix")
+		}
+	}
+}
+
+impl From<SocketAddr> for ClientAddr {
+	fn from(addr: SocketAddr) -> Self {
+		ClientAddr::Inet(addr)
+	}
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+/// A media type as found in a ``Content-Type`` header, e.g.
+/// ``multipart/form-data; boundary=something``.
+///
+/// The type and parameter names are compared case-insensitively, so they are
+/// stored in lower case. Quoted parameter values are unquoted.
+///
+/// # Example
+///
+/// ```
+/// use micro_http_server::MediaType;
+///
+/// let media_type = MediaType::parse("Text/Plain; charset=\"UTF-8\"").unwrap();
+/// assert_eq!("text/plain", media_type.essence());
+/// assert_eq!(Some("UTF-8"), media_type.param("Charset"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+	essence: String,
+	params: Vec<(String, String)>
+}
+
+impl MediaType {
+	/// Parse a media type, returning ``None`` if it does not consist of a
+	/// type and a subtype separated by ``/``.
+	pub fn parse(s: &str) -> Option<MediaType> {
+		let (essence, params) = match s.split_once(';') {
+			Some((essence, params)) => (essence, parse_parameters(params)),
+			None => (s, Vec::new())
+		};
+		let essence = essence.trim().to_ascii_lowercase();
+
+		let (main_type, sub_type) = essence.split_once('/')?;
+		if !is_token(main_type) || !is_token(sub_type) {
+			return None;
+		}
+
+		Some(MediaType {
+			essence,
+			params
+		})
+	}
+
+	/// Return the type and subtype without parameters, e.g. ``text/plain``.
+	pub fn essence(&self) -> &str {
+		&self.essence
+	}
+
+	/// Return the value of the parameter with the given name, if present.
+	pub fn param(&self, name: &str) -> Option<&str> {
+		self.params.iter()
+			.find(|(k, _)| k.eq_ignore_ascii_case(name))
+			.map(|(_, v)| v.as_str())
+	}
+
+	/// Return all parameters in the order they were given, with names in lower case.
+	pub fn params(&self) -> &[(String, String)] {
+		&self.params
+	}
+}
+
+impl fmt::Display for MediaType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.essence)?;
+		for (k, v) in &self.params {
+			if is_token(v) {
+				write!(f, "; {}={}", k, v)?;
+			} else {
+				write!(f, "; {}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\""))?;
+			}
+		}
+		Ok(())
+	}
+}
+
+// Check whether the string consists only of characters allowed in a token
+pub(crate) fn is_token(s: &str) -> bool {
+	!s.is_empty() && s.bytes().all(|b| {
+		b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+	})
+}
+
+// Parse parameters of the form `name=value; name="quoted value"`, as they are
+// used in the "Content-Type" and "Content-Disposition" headers. Parameters
+// without a value are skipped.
+pub(crate) fn parse_parameters(s: &str) -> Vec<(String, String)> {
+	let mut params = Vec::new();
+	let mut chars = s.chars().peekable();
+
+	loop {
+		// Name, up to the '='
+		let mut name = String::new();
+		let mut has_value = false;
+		for c in chars.by_ref() {
+			match c {
+				'=' => {
+					has_value = true;
+					break;
+				},
+				';' => name.clear(),
+				c => name.push(c)
+			}
+		}
+		let name = name.trim().to_ascii_lowercase();
+		if !has_value {
+			break;
+		}
+
+		// Value, up to the next ';' outside of quotes
+		let mut value = String::new();
+		while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+			chars.next();
+		}
+		if chars.peek() == Some(&'"') {
+			chars.next();
+			while let Some(c) = chars.next() {
+				match c {
+					'"' => break,
+					'\\' => value.extend(chars.next()),
+					c => value.push(c)
+				}
+			}
+			// Skip anything up to the next parameter
+			for c in chars.by_ref() {
+				if c == ';' { break; }
+			}
+		} else {
+			for c in chars.by_ref() {
+				if c == ';' { break; }
+				value.push(c);
+			}
+			value = value.trim().to_owned();
+		}
+
+		params.push((name, value));
+	}
+
+	params
+}
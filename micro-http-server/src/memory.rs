@@ -0,0 +1,62 @@
+use std::{
+	io::{self, Cursor, Read, Write},
+	sync::{Arc, Mutex},
+	time::Duration
+};
+use crate::transport::Transport;
+
+/// Everything a client created with ``Client::in_memory`` sent, i.e. the
+/// responses to its requests.
+///
+/// Clones share the same data, so the capture can be inspected after the
+/// client was passed on or dropped.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseCapture(Arc<Mutex<Vec<u8>>>);
+
+impl ResponseCapture {
+	/// Return everything that was sent so far.
+	pub fn bytes(&self) -> Vec<u8> {
+		self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+	}
+}
+
+// A connection which reads requests from memory and captures what is written
+#[derive(Debug)]
+pub(crate) struct MemoryStream {
+	input: Cursor<Vec<u8>>,
+	output: ResponseCapture
+}
+
+impl MemoryStream {
+	pub(crate) fn new(input: Vec<u8>) -> (MemoryStream, ResponseCapture) {
+		let output = ResponseCapture::default();
+		(MemoryStream { input: Cursor::new(input), output: output.clone() }, output)
+	}
+}
+
+impl Read for MemoryStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.input.read(buf)
+	}
+}
+
+impl Write for MemoryStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.output.0.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Transport for MemoryStream {
+	fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+		Ok(())
+	}
+
+	fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+		Ok(())
+	}
+}
@@ -1,11 +1,26 @@
-use std::{io, net::{TcpListener, ToSocketAddrs}};
+use std::{io, net::{TcpListener, ToSocketAddrs}, sync::Arc};
+#[cfg(unix)]
+use std::path::Path;
 
-use crate::client::Client;
+use crate::{client::Client, limits::Limits, listener::{self, ClientAddr, Listener}, proxy::{ProxiedStream, TrustedProxies}, stop::{StopHandle, StopState}, transport::Transport};
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
 
 /// This is the main struct of the µHTTP server.
 pub struct MicroHTTP {
-	// Internal listener which is used for the server part
-	listener: TcpListener,
+	// Internal listeners which are used for the server part
+	listeners: Vec<Listener>,
+	// Whether next_client returns right away if there is no client
+	nonblocking: bool,
+	// Set once the server stops accepting connections
+	stop: Arc<StopState>,
+	// Limits applied to new clients
+	limits: Limits,
+	// If set, connections from these proxies start with a PROXY protocol header
+	proxy_protocol: Option<TrustedProxies>,
+	// If set, connections are accepted with TLS
+	#[cfg(feature = "tls")]
+	tls: Option<TlsConfig>
 }
 
 impl MicroHTTP {
@@ -28,27 +43,163 @@ impl MicroHTTP {
 		let listener = TcpListener::bind(interface)?;
 
 		// Return created instance
+		MicroHTTP::from_listener(listener)
+	}
+
+	/// Create a new MicroHTTP server on a Unix domain socket at the given path.
+	/// See ``Listener::bind_unix`` for details.
+	///
+	/// # Example
+	///
+	/// ```no_run
+	/// use micro_http_server::MicroHTTP;
+	///
+	/// let server = MicroHTTP::new_unix("/run/user_sites.sock")
+	///     .expect("Could not create server.");
+	/// ```
+	#[cfg(unix)]
+	pub fn new_unix(path: impl AsRef<Path>) -> Result<MicroHTTP,io::Error> {
+		MicroHTTP::from_listener(Listener::bind_unix(path)?)
+	}
+
+	/// Create a new MicroHTTP server which accepts connections on a listener
+	/// that was created elsewhere, e.g. one passed by systemd (see
+	/// ``Listener::from_systemd``).
+	///
+	/// # Example
+	///
+	/// ```no_run
+	/// use micro_http_server::{Listener, MicroHTTP};
+	///
+	/// let servers = Listener::from_systemd()
+	///     .expect("Could not use the sockets passed by systemd.")
+	///     .into_iter()
+	///     .map(MicroHTTP::from_listener)
+	///     .collect::<Result<Vec<_>, _>>()
+	///     .expect("Could not create server.");
+	/// ```
+	pub fn from_listener(listener: impl Into<Listener>) -> Result<MicroHTTP,io::Error> {
+		let listener = listener.into();
+		// Listeners are waited for with poll, see next_client
+		listener.set_nonblocking(true)?;
+
 		Ok(MicroHTTP {
-			listener : listener
+			listeners: vec![listener],
+			nonblocking: false,
+			stop: Arc::new(StopState::new()?),
+			limits: Limits::default(),
+			proxy_protocol: None,
+			#[cfg(feature = "tls")]
+			tls: None
 		})
 	}
 
-	/// Set whether or not the underlying TcpListener awaits connections in nonblocking mode
+	/// Set the limits applied to clients accepted from now on. See ``Limits``
+	/// for the defaults.
+	pub fn set_limits(&mut self, limits: Limits) {
+		self.limits = limits;
+	}
+
+	/// Expect connections from the given proxies to start with a PROXY
+	/// protocol header (version 1 or 2), from now on, and use the address it
+	/// names as the client's (see ``Client::addr``). ``None`` doesn't expect
+	/// any headers. Connections from other clients are served as usual.
+	///
+	/// The header is read along with the client's first request. If it is
+	/// missing or invalid, ``Client::next_request`` fails. With TLS, the
+	/// header comes before the handshake, as proxies which pass on encrypted
+	/// connections send it.
+	pub fn set_proxy_protocol(&mut self, trusted: Option<TrustedProxies>) {
+		self.proxy_protocol = trusted;
+	}
+
+	/// Set the certificate used to accept clients with TLS from now on, or
+	/// ``None`` to accept plain connections. Requires the ``tls`` feature.
+	///
+	/// The TLS handshake happens when the client's first request is read, so
+	/// it doesn't hold up accepting other clients.
+	#[cfg(feature = "tls")]
+	pub fn set_tls(&mut self, tls: Option<TlsConfig>) {
+		self.tls = tls;
+	}
+
+	/// Accept connections on another listener as well. ``next_client`` returns
+	/// clients from whichever listener gets one first.
+	///
+	/// # Example
+	///
+	/// ```no_run
+	/// use micro_http_server::{Listener, MicroHTTP};
+	///
+	/// // Only accept connections from this host, over both IPv4 and IPv6
+	/// let mut server = MicroHTTP::new("127.0.0.1:3000").expect("Could not create server.");
+	/// server.add_listener(Listener::bind_tcp("[::1]:3000".parse().unwrap(), true).unwrap())
+	///     .expect("Could not add listener.");
+	/// ```
+	pub fn add_listener(&mut self, listener: impl Into<Listener>) -> Result<(), io::Error> {
+		let listener = listener.into();
+		listener.set_nonblocking(true)?;
+		self.listeners.push(listener);
+		Ok(())
+	}
+
+	/// Return the listeners the server accepts connections on, in the order
+	/// they were added.
+	pub fn listeners(&self) -> &[Listener] {
+		&self.listeners
+	}
+
+	/// Set whether or not ``next_client`` returns right away if no client is waiting
 	pub fn set_nonblocking(&mut self, state: bool) -> Result<(), io::Error> {
-		self.listener.set_nonblocking(state)
+		self.nonblocking = state;
+		Ok(())
+	}
+
+	/// Return a handle which stops the server from accepting connections, e.g.
+	/// from another thread when the process is asked to shut down.
+	///
+	/// # Example
+	///
+	/// ```
+	/// use micro_http_server::MicroHTTP;
+	///
+	/// let server = MicroHTTP::new("127.0.0.1:3001").expect("Could not create server.");
+	/// let stop = server.stop_handle();
+	/// std::thread::spawn(move || stop.stop());
+	///
+	/// // Returns None once the server is stopped
+	/// while let Some(client) = server.next_client().unwrap() {
+	///     // Serve the client
+	/// }
+	/// assert!(server.is_stopped());
+	/// ```
+	pub fn stop_handle(&self) -> StopHandle {
+		StopHandle(self.stop.clone())
+	}
+
+	/// Return whether the server was stopped with a ``StopHandle``.
+	pub fn is_stopped(&self) -> bool {
+		self.stop.is_stopped()
 	}
 
 
 	/// Return the next available client which is incoming at this server.
 	///
+	/// The client's request is not read here, so that a slow or misbehaving
+	/// client can't hold up accepting other clients; call ``Client::next_request``
+	/// (e.g. on another thread) to read it.
+	///
 	/// Returns either:
 	/// * ``Some(client)`` if a client is available
 	/// * ``None`` if no client is currently available (i.e. no one has reached out to the server yet)
-	/// * ``std::io::Error`` if something is wrong with the server.
+	///   in nonblocking mode, or if the server was stopped (see ``stop_handle``)
+	/// * ``std::io::Error`` if accepting the connection failed. This may be caused
+	///   by a client (e.g. one which reset the connection right away), so it is
+	///   usually fine to keep calling this afterwards.
 	///
 	/// # Example
 	///
-	/// ```
+	/// ```no_run
 	/// use std::{io::{Read,Write},net::TcpStream};
 	/// use micro_http_server::MicroHTTP;
 	///
@@ -75,11 +226,15 @@ impl MicroHTTP {
 	/// }
 	/// ```
 	pub fn next_client(&self) -> Result<Option<Client>,io::Error> {
+		if self.is_stopped() {
+			return Ok(None);
+		}
+
 		// See if we have any incoming connections.
-		match self.listener.accept() {
+		match listener::accept_any(&self.listeners, self.nonblocking, &self.stop) {
 			// We do - try to create a Client from the incoming socket & addr,
 			// then return it.
-			Ok( (socket, addr) ) => Ok(Some(Client::new(socket, addr)?)),
+			Ok( (socket, addr) ) => Ok(Some(self.client_for(socket, addr)?)),
 
 			// Check if we just don't have an incoming connection or
 			// if really an error occured.
@@ -89,36 +244,772 @@ impl MicroHTTP {
 			}
 		}
 	}
+
+	// Create a client for an accepted connection, with a PROXY protocol header
+	// and TLS if configured
+	pub(crate) fn client_for(&self, socket: Box<dyn Transport>, addr: ClientAddr) -> Result<Client,io::Error> {
+		let socket: Box<dyn Transport> = match &self.proxy_protocol {
+			Some(trusted) if trusted.contains(&addr) => Box::new(ProxiedStream::new(socket)),
+			_ => socket
+		};
+		#[cfg(feature = "tls")]
+		let socket = match &self.tls {
+			Some(tls) => Box::new(tls.accept(socket)?),
+			None => socket
+		};
+		Client::new(socket, addr, self.limits)
+	}
+
+	#[cfg(all(feature = "event-loop", unix))]
+	pub(crate) fn stop_state(&self) -> &StopState {
+		&self.stop
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::MicroHTTP;
-	use std::{io::{Read,Write},net::TcpStream};
+	use crate::Client;
+	use std::{io::{Read,Write},net::{SocketAddr,TcpStream}};
+
+	// Read everything the server sent, without the "Date" headers which
+	// change every second
+	fn read_without_date(connection: &mut impl Read) -> String {
+		let mut buf = String::new();
+		connection.read_to_string(&mut buf).unwrap();
+		buf.split_inclusive("\r\n")
+			.filter(|line| !line.starts_with("Date: "))
+			.collect()
+	}
+
+	// Return the address a TCP listener was bound to
+	fn local_addr(listener: &crate::Listener) -> SocketAddr {
+		match listener {
+			crate::Listener::Tcp(listener) => listener.local_addr().unwrap(),
+			#[cfg(unix)]
+			_ => unreachable!()
+		}
+	}
+
+	// Create a server on a port which is free, and return the port's address
+	fn local_server() -> (MicroHTTP, SocketAddr) {
+		let server = MicroHTTP::new("127.0.0.1:0").expect("Could not create server");
+		let addr = local_addr(&server.listeners()[0]);
+		(server, addr)
+	}
 
 	#[test]
 	fn echo() {
-		let server = MicroHTTP::new("127.0.0.1:65534").expect("Could not create server");
-		println!("Waiting for a client @ 127.0.0.1:65534...");
+		let (server, addr) = local_server();
+		println!("Waiting for a client @ {}...", addr);
 
-		let mut connection = TcpStream::connect("127.0.0.1:65534").expect("Could not reach server");
+		let mut connection = TcpStream::connect(addr).expect("Could not reach server");
 		println!("Connected!");
 
-		connection.write("GET /\r\n\r\n".as_bytes()).unwrap();
+		connection.write_all("GET /\r\n\r\n".as_bytes()).unwrap();
 
 		{
 			let opt = server.next_client().unwrap();
-			assert_eq!(true, opt.is_some());
+			assert!(opt.is_some());
 			let mut client = opt.unwrap();
+			assert!(client.next_request().unwrap());
 
 			println!("Got a client!");
-			assert_eq!(true, client.request().is_some());
-			assert_eq!("/", client.request().as_ref().unwrap());
+			assert!(client.request().is_some());
+			assert_eq!("/", client.request().as_ref().unwrap().path());
 			client.respond_ok("TEST".as_bytes()).unwrap();
 		}
 
-		let mut buf = String::new();
-		connection.read_to_string(&mut buf).unwrap();
-		assert_eq!("HTTP/1.0 200 OK\r\nContent-Length: 4\r\n\r\nTEST", buf);
+		let buf = read_without_date(&mut connection);
+		assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\nServer: micro_http_server\r\n\r\nTEST", buf);
+	}
+
+	#[test]
+	fn keep_alive_pipelined() {
+		let (mut client, capture) = Client::in_memory(concat!(
+			"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\n",
+			"GET /b HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"));
+
+		{
+			assert!(client.next_request().unwrap());
+			assert_eq!("/a", client.request().as_ref().unwrap().path());
+			assert!(client.keep_alive());
+			client.respond_ok("A".as_bytes()).unwrap();
+
+			assert!(client.next_request().unwrap());
+			assert_eq!("/b", client.request().as_ref().unwrap().path());
+			assert!(!client.keep_alive());
+			client.respond_ok("B".as_bytes()).unwrap();
+		}
+
+		let buf = read_without_date(&mut capture.bytes().as_slice());
+		assert_eq!(concat!(
+			"HTTP/1.1 200 OK\r\nContent-Length: 1\r\nServer: micro_http_server\r\n\r\nA",
+			"HTTP/1.1 200 OK\r\nContent-Length: 1\r\nConnection: close\r\nServer: micro_http_server\r\n\r\nB"), buf);
+	}
+
+	#[test]
+	fn reject() {
+		use crate::{Response, StatusCode};
+
+		let (server, addr) = local_server();
+		let mut connection = TcpStream::connect(addr).expect("Could not reach server");
+		// A body which is never read, which must not make the connection
+		// reset before the response is read
+		connection.write_all("POST / HTTP/1.1\r\nContent-Length: 65536\r\n\r\n".as_bytes()).unwrap();
+		connection.write_all(&[b'a'; 65536]).unwrap();
+
+		let client = server.next_client().unwrap().unwrap();
+		client.reject(Response::new(StatusCode::ServiceUnavailable)).unwrap();
+
+		let buf = read_without_date(&mut connection);
+		assert_eq!(
+			"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\nServer: micro_http_server\r\n\r\n",
+			buf);
+	}
+
+	#[test]
+	fn stream_chunked() {
+		let (mut client, capture) = Client::in_memory("GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+
+		{
+			assert!(client.next_request().unwrap());
+			client.respond_ok_stream("Hello, World!".as_bytes()).unwrap();
+		}
+
+		let buf = read_without_date(&mut capture.bytes().as_slice());
+		assert_eq!(concat!(
+			"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\nServer: micro_http_server\r\n\r\n",
+			"D\r\nHello, World!\r\n0\r\n\r\n"), buf);
+	}
+
+	#[test]
+	fn stream_http10() {
+		let (mut client, capture) = Client::in_memory("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+
+		{
+			assert!(client.next_request().unwrap());
+			assert!(client.keep_alive());
+			client.respond_ok_stream("Hello, World!".as_bytes()).unwrap();
+			assert!(!client.keep_alive());
+		}
+
+		let buf = read_without_date(&mut capture.bytes().as_slice());
+		assert_eq!("HTTP/1.1 200 OK\r\nConnection: close\r\nServer: micro_http_server\r\n\r\nHello, World!", buf);
+	}
+
+	#[test]
+	fn head() {
+		let (mut client, capture) = Client::in_memory(concat!(
+			"HEAD /a HTTP/1.1\r\n\r\n",
+			"HEAD /b HTTP/1.1\r\nConnection: close\r\n\r\n"));
+
+		{
+			assert!(client.next_request().unwrap());
+			client.respond_ok("TEST".as_bytes()).unwrap();
+			assert!(client.keep_alive());
+			assert!(client.next_request().unwrap());
+			client.respond_ok_stream("TEST".as_bytes()).unwrap();
+		}
+
+		let buf = read_without_date(&mut capture.bytes().as_slice());
+		assert_eq!(concat!(
+			"HTTP/1.1 200 OK\r\nContent-Length: 4\r\nServer: micro_http_server\r\n\r\n",
+			"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\nServer: micro_http_server\r\n\r\n"), buf);
+	}
+
+	#[test]
+	fn request_errors() {
+		use crate::RequestError;
+
+		let requests = [
+			("garbage\r\n\r\n", RequestError::BadRequest),
+			("GET /%FF HTTP/1.1\r\n\r\n", RequestError::BadRequest),
+			("GET / HTTP/2.0\r\n\r\n", RequestError::HTTPVersionNotSupported),
+			("GET / HTTP/1.x\r\n\r\n", RequestError::BadRequest),
+			("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n0\r\n\r\n", RequestError::BadRequest),
+			("POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\nHello", RequestError::BadRequest),
+			("PUT /a HTTP/1.1\r\n\r\n", RequestError::MethodNotAllowed("PUT".to_owned(), "/a".to_owned())),
+			("BREW /pot HTTP/1.1\r\n\r\n", RequestError::NotImplemented("BREW".to_owned()))
+		];
+
+		for (request, error) in requests {
+			let (mut client, _) = Client::in_memory(request);
+			assert!(client.next_request().unwrap());
+			assert!(client.request().is_none());
+			assert_eq!(Some(&error), client.request_error());
+			assert!(!client.keep_alive());
+		}
+
+		// Requests which end before their headers do
+		for request in ["GET / HTTP/1.1\r\n", "GET / HTTP/1.1\r\nHost: a\r\n", "GET / HTTP/1.1\r\nHost: a"] {
+			let (mut client, _) = Client::in_memory(request);
+			assert!(client.next_request().unwrap());
+			assert!(client.request().is_none());
+			assert_eq!(Some(&RequestError::BadRequest), client.request_error());
+		}
+	}
+
+	#[test]
+	fn limits() {
+		use crate::{Limits, RequestError};
+		use std::time::Duration;
+
+		let limits = Limits {
+			header_read_timeout: Some(Duration::from_millis(100)),
+			max_request_line: 32,
+			max_headers: 2,
+			max_header_size: 64,
+			max_body_size: 4,
+			..Limits::default()
+		};
+		let requests = [
+			("GET /a-very-long-path-indeed HTTP/1.1\r\n\r\n", RequestError::URITooLong),
+			("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n", RequestError::RequestHeaderFieldsTooLarge),
+			("GET / HTTP/1.1\r\nA: 0123456789012345678901234567890123456789012345678901234567890123\r\n\r\n",
+				RequestError::RequestHeaderFieldsTooLarge),
+			// Empty lines before the request count towards the headers
+			("\r\n\r\nGET / HTTP/1.1\r\nA: 0123456789012345678901234567890123456789012345678901234567\r\n\r\n",
+				RequestError::RequestHeaderFieldsTooLarge),
+			("\r\n\r\n\r\n\r\n\r\nGET / HTTP/1.1\r\n\r\n", RequestError::BadRequest),
+			("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello", RequestError::PayloadTooLarge)
+		];
+
+		for (request, error) in requests {
+			let (mut client, _) = Client::in_memory(request);
+			client.set_limits(limits).unwrap();
+			assert!(client.next_request().unwrap());
+			assert_eq!(Some(&error), client.request_error());
+		}
+
+		// Timeouts need a connection which stays open
+		let (mut server, addr) = local_server();
+		server.set_limits(limits);
+		let mut connection = TcpStream::connect(addr).expect("Could not reach server");
+		connection.write_all("GET / HTTP/1.1\r\n".as_bytes()).unwrap();
+
+		let mut client = server.next_client().unwrap().unwrap();
+		assert!(client.next_request().unwrap());
+		assert_eq!(Some(&RequestError::RequestTimeout), client.request_error());
+	}
+
+	#[test]
+	fn body() {
+		use crate::{FormData, Method, RequestError};
+
+		let (mut client, _) = Client::in_memory(concat!(
+			"POST /a HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 7\r\n\r\na=1&b=2",
+			"POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nHello\r\n8\r\n, World!\r\n0\r\nTrailer: x\r\n\r\n",
+			"POST /c HTTP/1.1\r\nContent-Length: 6\r\n\r\nunread",
+			"POST /d HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nZZ\r\n"));
+
+		assert!(client.next_request().unwrap());
+		let request = client.request_mut().take().unwrap();
+		assert_eq!(Method::POST, request.method());
+		match client.form_data(request.headers()).unwrap() {
+			Some(FormData::KeyVal(data)) => {
+				assert_eq!(Some("1"), data.get("a"));
+				assert_eq!(Some("2"), data.get("b"));
+			},
+			_ => panic!("Expected key-value form data")
+		}
+
+		assert!(client.next_request().unwrap());
+		let mut body = String::new();
+		client.body().read_to_string(&mut body).unwrap();
+		assert_eq!("Hello, World!", body);
+
+		assert!(client.next_request().unwrap());
+		assert_eq!("/c", client.request().as_ref().unwrap().path());
+
+		assert!(client.next_request().unwrap());
+		let e = client.body().read_to_end(&mut Vec::new()).unwrap_err();
+		assert_eq!(Some(&RequestError::BadRequest), RequestError::from_io_error(&e));
+	}
+
+	#[test]
+	fn malformed_chunks() {
+		use crate::{Limits, RequestError};
+
+		let requests = [
+			"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+5\r\nHello\r\n0\r\n\r\n",
+			// Trailers larger than the headers may be
+			"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nA: 0123456789\r\nB: 0123456789\r\nC: 0123456789\r\n\r\n"
+		];
+		for request in requests {
+			let (mut client, _) = Client::in_memory(request);
+			client.set_limits(Limits { max_header_size: 40, ..Limits::default() }).unwrap();
+			assert!(client.next_request().unwrap());
+			let e = client.body().read_to_end(&mut Vec::new()).unwrap_err();
+			assert_eq!(Some(&RequestError::BadRequest), RequestError::from_io_error(&e));
+		}
+	}
+
+	#[test]
+	fn expect_continue() {
+		let (server, addr) = local_server();
+		let mut connection = TcpStream::connect(addr).expect("Could not reach server");
+
+		connection.write_all(
+			"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n".as_bytes()).unwrap();
+
+		let mut client = server.next_client().unwrap().unwrap();
+		assert!(client.next_request().unwrap());
+
+		let mut response = [0; 25];
+		let mut body = [0; 5];
+		let mut reader = client.body();
+		std::thread::scope(|s| {
+			s.spawn(|| {
+				connection.read_exact(&mut response).unwrap();
+				connection.write_all("Hello".as_bytes()).unwrap();
+			});
+			reader.read_exact(&mut body).unwrap();
+		});
+		assert_eq!("HTTP/1.1 100 Continue\r\n\r\n".as_bytes(), &response);
+		assert_eq!("Hello".as_bytes(), &body);
+	}
+
+	#[test]
+	fn multipart() {
+		use crate::{FormData, Method};
+
+		let body = concat!(
+			"preamble\r\n--xyz\r\n",
+			"Content-Disposition: form-data; name=\"field\"\r\n\r\n",
+			"value\r\n--xyz\r\n",
+			"Content-Disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".txt\"\r\n",
+			"Content-Type: text/plain\r\n\r\n",
+			"line 1\r\n--xy line 2\r\n--xyz--\r\nepilogue");
+		let (mut client, _) = Client::in_memory(format!(
+			"POST / HTTP/1.1\r\nContent-Type: Multipart/Form-Data; boundary=\"xyz\"\r\nContent-Length: {}\r\n\r\n{}{}",
+			body.len(), body,
+			"POST / HTTP/1.1\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 5\r\n\r\nHello"));
+
+		assert!(client.next_request().unwrap());
+		let request = client.request_mut().take().unwrap();
+		assert_eq!(Method::POST, request.method());
+		let mut multipart = match client.form_data(request.headers()).unwrap() {
+			Some(FormData::Multipart(multipart)) => multipart,
+			_ => panic!("Expected multipart form data")
+		};
+
+		let mut part = multipart.next_part().unwrap().unwrap();
+		assert_eq!(Some("field"), part.name());
+		assert_eq!(None, part.filename());
+		let mut value = String::new();
+		part.read_to_string(&mut value).unwrap();
+		assert_eq!("value", value);
+
+		let mut part = multipart.next_part().unwrap().unwrap();
+		assert_eq!(Some("file"), part.name());
+		assert_eq!(Some("a \"b\".txt"), part.filename());
+		assert_eq!("text/plain", part.content_type().unwrap().essence());
+		let mut value = String::new();
+		part.read_to_string(&mut value).unwrap();
+		assert_eq!("line 1\r\n--xy line 2", value);
+
+		assert!(multipart.next_part().unwrap().is_none());
+
+		assert!(client.next_request().unwrap());
+		let request = client.request_mut().take().unwrap();
+		assert_eq!(Method::POST, request.method());
+		match client.form_data(request.headers()).unwrap() {
+			Some(FormData::Text(text)) => assert_eq!("Hello", text),
+			_ => panic!("Expected plain text form data")
+		}
+	}
+
+	#[test]
+	fn response() {
+		use crate::{Response, StatusCode};
+
+		let (mut client, capture) = Client::in_memory(concat!(
+			"GET /a HTTP/1.1\r\n\r\n",
+			"GET /b HTTP/1.1\r\n\r\n",
+			"GET /c HTTP/1.1\r\n\r\n"));
+
+		{
+			assert!(client.next_request().unwrap());
+			client.send(Response::new(StatusCode::NotFound)
+				.header("cache-control", "no-cache")
+				.header("Cache-Control", "max-age=30")
+				.header("Content-Length", "100")
+				.header("Server", "test")
+				.body("Nothing".as_bytes())).unwrap();
+
+			assert!(client.next_request().unwrap());
+			client.send(Response::new(StatusCode::NotModified)
+				.body("ignored".as_bytes())).unwrap();
+			assert!(client.keep_alive());
+
+			assert!(client.next_request().unwrap());
+			assert!(client.send(Response::ok().header("Bad\r\nHeader", "x")).is_err());
+			client.send(Response::ok()
+				.header("Connection", "close")
+				.body_reader("Hello".as_bytes(), 5)).unwrap();
+			assert!(!client.keep_alive());
+		}
+
+		let buf = String::from_utf8(capture.bytes()).unwrap();
+		assert_eq!(3, buf.matches("\r\nDate: ").count());
+		let buf = buf.split_inclusive("\r\n")
+			.filter(|line| !line.starts_with("Date: "))
+			.collect::<String>();
+		assert_eq!(concat!(
+			"HTTP/1.1 404 Not Found\r\nContent-Length: 7\r\nCache-Control: max-age=30\r\nServer: test\r\n\r\nNothing",
+			"HTTP/1.1 304 Not Modified\r\nServer: micro_http_server\r\n\r\n",
+			"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\nServer: micro_http_server\r\n\r\nHello"), buf);
+	}
+
+	#[test]
+	fn request() {
+		use crate::{Method, Version};
+
+		let (mut client, _) = Client::in_memory(concat!(
+			"GET http://localhost/a%20b/c+d?tag=a&tag=b&q=x+y%26z&flag HTTP/1.1\r\n\r\n",
+			"OPTIONS * HTTP/1.0\r\n\r\n"));
+
+		assert!(client.next_request().unwrap());
+		let request = client.request().as_ref().unwrap();
+		assert_eq!(Method::GET, request.method());
+		assert_eq!(Version::HTTP11, request.version());
+		assert_eq!("http://localhost/a%20b/c+d?tag=a&tag=b&q=x+y%26z&flag", request.target());
+		assert_eq!("/a b/c+d", request.path());
+		assert_eq!("/a%20b/c+d", request.raw_path());
+		assert_eq!(Some("tag=a&tag=b&q=x+y%26z&flag"), request.query_string());
+		assert_eq!(
+			vec![("tag", "a"), ("tag", "b"), ("q", "x y&z"), ("flag", "")],
+			request.query().iter().collect::<Vec<_>>());
+
+		assert!(client.next_request().unwrap());
+		let request = client.request().as_ref().unwrap();
+		assert_eq!(Method::OPTIONS, request.method());
+		assert_eq!(Version::HTTP10, request.version());
+		assert_eq!("*", request.path());
+		assert_eq!(None, request.query_string());
+		assert!(request.query().is_empty());
+	}
+
+	#[test]
+	fn headers() {
+		use crate::RequestError;
+
+		let (mut client, _) = Client::in_memory(concat!(
+			"GET / HTTP/1.1\r\n",
+			"Cookie: a=1\r\n",
+			"X-Compact:value\r\n",
+			"X-Spaced \t:\t value \r\n",
+			"Accept: text/html,\r\n",
+			"  text/plain;q=0.5\r\n",
+			"cookie: b=2\r\n",
+			"X-Forwarded-For: 1.1.1.1, 2.2.2.2\r\n",
+			"X-Forwarded-For: 3.3.3.3\r\n\r\n"));
+
+		assert!(client.next_request().unwrap());
+		let headers = client.request().as_ref().unwrap().headers();
+		assert_eq!(vec!["a=1", "b=2"], headers.get_all("COOKIE").collect::<Vec<_>>());
+		assert_eq!(Some("value"), headers.get("x-compact"));
+		assert_eq!(Some("value"), headers.get("x-spaced"));
+		assert_eq!(Some("text/html, text/plain;q=0.5"), headers.get("accept"));
+		assert_eq!(
+			vec!["1.1.1.1", "2.2.2.2", "3.3.3.3"],
+			headers.get_list("x-forwarded-for").collect::<Vec<_>>());
+		assert_eq!(Some(("X-Compact", "value")), headers.iter().nth(1));
+
+		let requests = [
+			"GET / HTTP/1.1\r\nBad Name: x\r\n\r\n",
+			"GET / HTTP/1.1\r\nX: a\x01b\r\n\r\n",
+			"GET / HTTP/1.1\r\n folded: x\r\n\r\n",
+			"GET / HTTP/1.1\r\nNo colon\r\n\r\n",
+			"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab"
+		];
+		for request in requests {
+			let (mut client, _) = Client::in_memory(request);
+			assert!(client.next_request().unwrap());
+			assert_eq!(Some(&RequestError::BadRequest), client.request_error());
+		}
+	}
+
+	#[cfg(feature = "tls")]
+	#[test]
+	fn tls() {
+		use crate::{Limits, Response, StatusCode, TlsConfig};
+		use std::time::Duration;
+		use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+		use std::sync::Arc;
+
+		let (mut server, addr) = local_server();
+		let dir = std::env::temp_dir().join(format!("micro_http_server-tls-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let cert_path = dir.join("cert.pem");
+		let key_path = dir.join("key.pem");
+
+		// Write a new self-signed certificate and return it
+		let new_cert = || {
+			let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+			std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+			std::fs::write(&key_path, certified.signing_key.serialize_pem()).unwrap();
+			certified.cert.der().clone()
+		};
+		// Request "/" over TLS, trusting only the given certificate
+		let request = |cert| {
+			let mut roots = RootCertStore::empty();
+			roots.add(cert).unwrap();
+			let provider = Arc::new(rustls::crypto::ring::default_provider());
+			let config = ClientConfig::builder_with_provider(provider)
+				.with_safe_default_protocol_versions().unwrap()
+				.with_root_certificates(roots)
+				.with_no_client_auth();
+			let connection = ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+			let mut stream = StreamOwned::new(
+				connection, TcpStream::connect(addr).expect("Could not reach server"));
+
+			stream.write_all("GET / HTTP/1.1\r\nConnection: close\r\n\r\n".as_bytes())?;
+			let mut buf = String::new();
+			stream.read_to_string(&mut buf)?;
+			Ok::<_, std::io::Error>(buf)
+		};
+
+		let first_cert = new_cert();
+		let tls = TlsConfig::from_pem_files(&cert_path, &key_path).unwrap();
+		server.set_tls(Some(tls.clone()));
+
+		let serve = |server: &MicroHTTP| {
+			let mut client = server.next_client().unwrap().unwrap();
+			if client.next_request().unwrap_or(false) {
+				client.respond_ok("TLS".as_bytes()).unwrap();
+			}
+		};
+
+		std::thread::scope(|s| {
+			s.spawn(|| serve(&server));
+			assert!(request(first_cert.clone()).unwrap().ends_with("\r\n\r\nTLS"));
+		});
+
+		// After reloading, the new certificate is used
+		let second_cert = new_cert();
+		tls.reload().unwrap();
+		std::thread::scope(|s| {
+			s.spawn(|| serve(&server));
+			assert!(request(first_cert).is_err());
+		});
+		std::thread::scope(|s| {
+			s.spawn(|| serve(&server));
+			assert!(request(second_cert).unwrap().ends_with("\r\n\r\nTLS"));
+		});
+
+		// Turning a client away gives up on a handshake which never starts
+		server.set_limits(Limits { header_read_timeout: Some(Duration::from_millis(100)), ..Limits::default() });
+		let _connection = TcpStream::connect(addr).expect("Could not reach server");
+		let client = server.next_client().unwrap().unwrap();
+		assert!(client.reject(Response::new(StatusCode::ServiceUnavailable)).is_err());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn unix_socket() {
+		use crate::ClientAddr;
+		use std::os::unix::net::{UnixListener, UnixStream};
+
+		let dir = std::env::temp_dir().join(format!("micro_http_server-unix-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("server.sock");
+
+		// A socket left over from a previous run is replaced
+		drop(UnixListener::bind(&path).unwrap());
+		let server = MicroHTTP::new_unix(&path).expect("Could not create server");
+		let mut connection = UnixStream::connect(&path).expect("Could not reach server");
+		connection.write_all("GET /unix HTTP/1.1\r\nConnection: close\r\n\r\n".as_bytes()).unwrap();
+
+		{
+			let mut client = server.next_client().unwrap().unwrap();
+			assert_eq!(&ClientAddr::Unix(None), client.addr());
+			assert!(client.next_request().unwrap());
+			assert_eq!("/unix", client.request().as_ref().unwrap().path());
+			client.respond_ok("UNIX".as_bytes()).unwrap();
+		}
+
+		let buf = read_without_date(&mut connection);
+		assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\nServer: micro_http_server\r\n\r\nUNIX", buf);
+
+		// Other files are left alone
+		drop(server);
+		std::fs::remove_file(&path).unwrap();
+		std::fs::write(&path, "").unwrap();
+		assert!(MicroHTTP::new_unix(&path).is_err());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn multiple_listeners() {
+		use crate::{ClientAddr, Listener};
+		use std::net::IpAddr;
+
+		let (mut server, ipv4_addr) = local_server();
+		let ipv6 = Listener::bind_tcp("[::1]:0".parse().unwrap(), true).unwrap();
+		let ipv6_addr = local_addr(&ipv6);
+		server.add_listener(ipv6).unwrap();
+		// Dual-stack, so IPv4 clients are accepted as well
+		let dual_stack = Listener::bind_tcp("[::]:0".parse().unwrap(), false).unwrap();
+		let dual_stack_addr = SocketAddr::from(([127, 0, 0, 1], local_addr(&dual_stack).port()));
+		server.add_listener(dual_stack).unwrap();
+
+		server.set_nonblocking(true).unwrap();
+		assert!(server.next_client().unwrap().is_none());
+		server.set_nonblocking(false).unwrap();
+
+		for (addr, client_ip) in [
+			(ipv4_addr, "127.0.0.1"),
+			(ipv6_addr, "::1"),
+			(dual_stack_addr, "::ffff:127.0.0.1")
+		] {
+			let mut connection = TcpStream::connect(addr).expect("Could not reach server");
+			connection.write_all("GET / HTTP/1.1\r\nConnection: close\r\n\r\n".as_bytes()).unwrap();
+
+			{
+				let mut client = server.next_client().unwrap().unwrap();
+				match client.addr() {
+					ClientAddr::Inet(addr) => assert_eq!(client_ip.parse::<IpAddr>().unwrap(), addr.ip()),
+					addr => panic!("Unexpected address: {}", addr)
+				}
+				assert!(client.next_request().unwrap());
+				client.respond_ok(addr.to_string().as_bytes()).unwrap();
+			}
+
+			assert!(read_without_date(&mut connection).ends_with(&addr.to_string()));
+		}
+	}
+
+	#[test]
+	fn proxy_protocol() {
+		use crate::{ClientAddr, TrustedProxies};
+
+		let (mut server, addr) = local_server();
+		server.set_proxy_protocol(Some(TrustedProxies {
+			addrs: vec!["127.0.0.1".parse().unwrap()],
+			..TrustedProxies::default()
+		}));
+
+		// From [2001:db8::1]:9419 to [::]:443
+		let v2_header = [
+			&b"\r\n\r\n\0\r\nQUIT\n\x21\x21\x00\x24"[..],
+			&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01],
+			&[0; 16],
+			&[0x24, 0xcb, 0x01, 0xbb]
+		].concat();
+		for (header, client_addr) in [
+			(&b"PROXY TCP4 192.0.2.1 127.0.0.1 9435 65516\r\n"[..], Some("192.0.2.1:9435")),
+			(&v2_header, Some("[2001:db8::1]:9419")),
+			// Without a source address, the proxy's is kept
+			(b"PROXY UNKNOWN\r\n", None),
+			(b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00", None)
+		] {
+			let mut connection = TcpStream::connect(addr).expect("Could not reach server");
+			let proxy = ClientAddr::Inet(connection.local_addr().unwrap());
+			connection.write_all(&[header, b"GET /proxied HTTP/1.1\r\nConnection: close\r\n\r\n"].concat()).unwrap();
+
+			let mut client = server.next_client().unwrap().unwrap();
+			assert!(client.next_request().unwrap());
+			assert_eq!("/proxied", client.request().as_ref().unwrap().path());
+			let client_addr = client_addr.map(|addr| ClientAddr::Inet(addr.parse().unwrap()));
+			assert_eq!(client_addr.as_ref().unwrap_or(&proxy), client.addr());
+			assert_eq!(&proxy, client.peer_addr());
+		}
+
+		// Trusted proxies have to send a valid header
+		for header in [&b"PROXY TCP4 192.0.2.1\r\n"[..], b""] {
+			let mut connection = TcpStream::connect(addr).expect("Could not reach server");
+			connection.write_all(&[header, b"GET / HTTP/1.1\r\n\r\n"].concat()).unwrap();
+			assert!(server.next_client().unwrap().unwrap().next_request().is_err());
+		}
+
+		// Other clients don't send one
+		server.set_proxy_protocol(Some(TrustedProxies {
+			addrs: vec!["192.0.2.1".parse().unwrap()],
+			..TrustedProxies::default()
+		}));
+		let mut connection = TcpStream::connect(addr).expect("Could not reach server");
+		connection.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+		let mut client = server.next_client().unwrap().unwrap();
+		assert!(client.next_request().unwrap());
+		assert_eq!(&ClientAddr::Inet(connection.local_addr().unwrap()), client.addr());
+	}
+
+	#[test]
+	fn stop() {
+		let (server, addr) = local_server();
+		let stop = server.stop_handle();
+		assert!(!server.is_stopped());
+
+		std::thread::scope(|s| {
+			let waiting = s.spawn(|| server.next_client().unwrap().is_none());
+			std::thread::sleep(std::time::Duration::from_millis(50));
+			stop.stop();
+			assert!(waiting.join().unwrap());
+		});
+
+		// Connections are no longer accepted
+		let _connection = TcpStream::connect(addr).expect("Could not reach server");
+		assert!(server.is_stopped());
+		assert!(server.next_client().unwrap().is_none());
+	}
+
+	#[cfg(all(feature = "event-loop", unix))]
+	#[test]
+	fn event_loop() {
+		use crate::{EventLoop, Response};
+		use std::{io::Cursor, sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+
+		let (server, addr) = local_server();
+		let stop = server.stop_handle();
+		let dispatched = AtomicUsize::new(0);
+
+		std::thread::scope(|s| {
+			let event_loop = s.spawn(|| EventLoop::new().unwrap().run(&[&server], |mut client| {
+				dispatched.fetch_add(1, Ordering::SeqCst);
+				s.spawn(move || {
+					assert!(client.has_event_loop());
+					assert!(client.next_request().unwrap());
+					let body = Cursor::new(client.request().as_ref().unwrap().path().as_bytes().to_vec());
+					let length = body.get_ref().len();
+					// Sent with a length, then in chunks
+					let length = if length == 2 { Some(length) } else { None };
+					client.send(Response::ok().body_file(body, length)).unwrap();
+				});
+			}));
+
+			let mut connection = TcpStream::connect(addr).expect("Could not reach server");
+			connection.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+			connection.write_all("GET /a HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+			let mut buf = Vec::new();
+			while !buf.ends_with(b"/a") {
+				let mut chunk = [0; 1024];
+				let bytes_read = connection.read(&mut chunk).unwrap();
+				assert!(bytes_read > 0);
+				buf.extend_from_slice(&chunk[..bytes_read]);
+			}
+			let buf = read_without_date(&mut buf.as_slice());
+			assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 2\r\nServer: micro_http_server\r\n\r\n/a", buf);
+
+			// The connection waits in the event loop for the next requests,
+			// which are pipelined
+			std::thread::sleep(Duration::from_millis(50));
+			connection.write_all(concat!(
+				"GET /b HTTP/1.1\r\n\r\n",
+				"GET /cc HTTP/1.1\r\nConnection: close\r\n\r\n").as_bytes()).unwrap();
+			let buf = read_without_date(&mut connection);
+			assert_eq!(concat!(
+				"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nServer: micro_http_server\r\n\r\n/b",
+				"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\nServer: micro_http_server\r\n\r\n",
+				"3\r\n/cc\r\n0\r\n\r\n"), buf);
+
+			stop.stop();
+			event_loop.join().unwrap().unwrap();
+		});
+		assert_eq!(3, dispatched.load(Ordering::SeqCst));
 	}
 }
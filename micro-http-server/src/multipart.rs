@@ -0,0 +1,260 @@
+use std::{
+	io::{self, Read},
+	cmp
+};
+use crate::client::Headers;
+use crate::error::RequestError;
+use crate::media_type::{MediaType, parse_parameters};
+
+// How many bytes are read from the underlying reader at once
+const READ_SIZE: usize = 8 * 1024;
+
+// The maximum size of the headers of a single part
+const MAX_PART_HEADER_SIZE: usize = 16 * 1024;
+
+/// A streaming parser for ``multipart/form-data`` bodies.
+///
+/// Parts are returned one after another by ``next_part``, and the body of each
+/// part is read from the returned ``Part``, so large file uploads don't have
+/// to be held in memory.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+/// use micro_http_server::Multipart;
+///
+/// let body = "--b\r\nContent-Disposition: form-data; name=\"greeting\"\r\n\r\nHello\r\n--b--\r\n";
+/// let mut multipart = Multipart::new(body.as_bytes(), "b");
+///
+/// let mut part = multipart.next_part().unwrap().unwrap();
+/// assert_eq!(Some("greeting"), part.name());
+/// let mut value = String::new();
+/// part.read_to_string(&mut value).unwrap();
+/// assert_eq!("Hello", value);
+///
+/// assert!(multipart.next_part().unwrap().is_none());
+/// ```
+#[derive(Debug)]
+pub struct Multipart<R> {
+	reader: R,
+	buffer: Vec<u8>,
+	// Start of the data in the buffer which hasn't been consumed yet
+	start: usize,
+	// "\r\n--" followed by the boundary
+	delimiter: Vec<u8>,
+	// Whether the underlying reader has no more data
+	is_eof: bool,
+	// Whether the body of the current part has been read up to the delimiter
+	is_part_done: bool,
+	// Whether the closing delimiter has been read
+	is_done: bool
+}
+
+/// A part of a ``multipart/form-data`` body, obtained from ``Multipart::next_part``.
+///
+/// Reading from it yields the part's body.
+#[derive(Debug)]
+pub struct Part<'a, R> {
+	multipart: &'a mut Multipart<R>,
+	headers: Headers,
+	name: Option<String>,
+	filename: Option<String>,
+	content_type: Option<MediaType>
+}
+
+impl<R: Read> Multipart<R> {
+	/// Create a parser reading a multipart body from ``reader``, whose parts
+	/// are separated by ``boundary`` (the ``boundary`` parameter of the
+	/// ``Content-Type`` header).
+	pub fn new(reader: R, boundary: &str) -> Self {
+		let mut delimiter = b"\r\n--".to_vec();
+		delimiter.extend_from_slice(boundary.as_bytes());
+
+		Multipart {
+			reader,
+			// The first delimiter is not preceded by a line break, so pretend
+			// there is one to find it the same way as the others.
+			buffer: b"\r\n".to_vec(),
+			start: 0,
+			delimiter,
+			is_eof: false,
+			is_part_done: false,
+			is_done: false
+		}
+	}
+
+	/// Return the next part of the body, skipping whatever is left of the
+	/// previous one. Returns ``None`` after the last part.
+	///
+	/// Malformed bodies result in an ``std::io::Error`` wrapping
+	/// ``RequestError::BadRequest``.
+	pub fn next_part(&mut self) -> io::Result<Option<Part<'_, R>>> {
+		if self.is_done {
+			return Ok(None);
+		}
+
+		// Skip the rest of the current part (or the preamble) and the delimiter
+		self.is_part_done = false;
+		let mut buffer = [0; READ_SIZE];
+		while self.read_part(&mut buffer)? > 0 {}
+		self.consume(self.delimiter.len());
+
+		// The closing delimiter is followed by "--"
+		self.fill(2)?;
+		if self.available().starts_with(b"--") {
+			self.is_done = true;
+			return Ok(None);
+		}
+		self.read_line()?;
+
+		let mut headers = Headers::new();
+		let mut header_size = 0;
+		loop {
+			let line = self.read_line()?;
+			header_size += line.len();
+			if header_size > MAX_PART_HEADER_SIZE {
+				return Err(RequestError::BadRequest.into());
+			}
+
+			let line = String::from_utf8_lossy(&line);
+			if line.trim().is_empty() {
+				break;
+			}
+			if let Some((k, v)) = line.split_once(':') {
+				headers.append(k.trim(), v.trim());
+			}
+		}
+
+		let disposition = headers.get("content-disposition")
+			.map(|d| parse_parameters(d.split_once(';').map(|(_, p)| p).unwrap_or("")))
+			.unwrap_or_default();
+		let param = |name: &str| disposition.iter()
+			.find(|(k, _)| k == name)
+			.map(|(_, v)| v.to_owned());
+		let name = param("name");
+		let filename = param("filename");
+		let content_type = headers.get("content-type").and_then(MediaType::parse);
+
+		self.is_part_done = false;
+		Ok(Some(Part {
+			multipart: self,
+			headers,
+			name,
+			filename,
+			content_type
+		}))
+	}
+
+	fn available(&self) -> &[u8] {
+		&self.buffer[self.start..]
+	}
+
+	fn consume(&mut self, length: usize) {
+		self.start = cmp::min(self.start + length, self.buffer.len());
+	}
+
+	// Read from the underlying reader until at least ``length`` bytes are
+	// available or there is no more data.
+	fn fill(&mut self, length: usize) -> io::Result<()> {
+		while self.available().len() < length && !self.is_eof {
+			// Move the remaining data to the front instead of growing the buffer
+			if self.start > 0 {
+				self.buffer.drain(..self.start);
+				self.start = 0;
+			}
+
+			let old_len = self.buffer.len();
+			self.buffer.resize(old_len + READ_SIZE, 0);
+			let bytes_read = self.reader.read(&mut self.buffer[old_len..]);
+			self.buffer.truncate(old_len + *bytes_read.as_ref().unwrap_or(&0));
+			if bytes_read? == 0 {
+				self.is_eof = true;
+			}
+		}
+		Ok(())
+	}
+
+	// Read a line of part headers, including the line ending
+	fn read_line(&mut self) -> io::Result<Vec<u8>> {
+		let mut length = 0;
+		loop {
+			if let Some(i) = self.available()[length..].iter().position(|&b| b == b'\n') {
+				let line = self.available()[..length + i + 1].to_vec();
+				self.consume(line.len());
+				return Ok(line);
+			}
+
+			length = self.available().len();
+			if length > MAX_PART_HEADER_SIZE || self.is_eof {
+				return Err(RequestError::BadRequest.into());
+			}
+			self.fill(length + 1)?;
+		}
+	}
+
+	// Read the body of the current part, up to the next delimiter
+	fn read_part(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.is_part_done || buf.is_empty() {
+			return Ok(0);
+		}
+
+		loop {
+			self.fill(self.delimiter.len() + READ_SIZE)?;
+			let available = &self.buffer[self.start..];
+
+			let length = match find(available, &self.delimiter) {
+				Some(0) => {
+					self.is_part_done = true;
+					return Ok(0);
+				},
+				Some(i) => i,
+				// Keep enough data to find a delimiter which has only partially
+				// arrived yet.
+				None if !self.is_eof => available.len().saturating_sub(self.delimiter.len() - 1),
+				None => return Err(RequestError::BadRequest.into())
+			};
+
+			if length > 0 {
+				let length = cmp::min(length, buf.len());
+				buf[..length].copy_from_slice(&available[..length]);
+				self.consume(length);
+				return Ok(length);
+			}
+		}
+	}
+}
+
+impl<R> Part<'_, R> {
+	/// Return the headers of this part.
+	pub fn headers(&self) -> &Headers {
+		&self.headers
+	}
+
+	/// Return the name of the form field this part belongs to, from the
+	/// ``Content-Disposition`` header.
+	pub fn name(&self) -> Option<&str> {
+		self.name.as_deref()
+	}
+
+	/// Return the name of the uploaded file, if this part contains a file.
+	pub fn filename(&self) -> Option<&str> {
+		self.filename.as_deref()
+	}
+
+	/// Return the media type of this part's body, if given.
+	pub fn content_type(&self) -> Option<&MediaType> {
+		self.content_type.as_ref()
+	}
+}
+
+impl<R: Read> Read for Part<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.multipart.read_part(buf)
+	}
+}
+
+// Return the position of the first occurrence of needle in haystack
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	haystack.windows(needle.len()).position(|w| w == needle)
+}
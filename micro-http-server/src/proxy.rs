@@ -0,0 +1,249 @@
+use std::{
+	fmt,
+	io::{self, Read, Write},
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	str,
+	time::Duration
+};
+use crate::{listener::ClientAddr, transport::Transport};
+
+// The start of a version 1 header, which is a line of text
+const V1_SIGNATURE: &[u8] = b"PROXY ";
+// The longest version 1 header, including the line ending
+const V1_MAX_LENGTH: usize = 107;
+// The start of a version 2 header, which is binary
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+// The length of a version 2 header without the addresses
+const V2_FIXED_LENGTH: usize = 16;
+
+/// The clients which are proxies, e.g. HAProxy or nginx, and start each
+/// connection with a PROXY protocol header (version 1 or 2) naming the client
+/// they accepted the connection from. See ``MicroHTTP::set_proxy_protocol``.
+///
+/// # Example
+///
+/// ```
+/// use micro_http_server::{MicroHTTP, TrustedProxies};
+///
+/// let mut server = MicroHTTP::new("127.0.0.1:3002").expect("Could not create server.");
+/// server.set_proxy_protocol(Some(TrustedProxies {
+///     addrs: vec!["127.0.0.1".parse().unwrap()],
+///     ..TrustedProxies::default()
+/// }));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+	/// The IP addresses of proxies connecting over TCP. IPv4 addresses also
+	/// match the IPv4-mapped IPv6 addresses of clients on dual-stack sockets.
+	pub addrs: Vec<IpAddr>,
+	/// Whether all clients connecting over Unix domain sockets are proxies.
+	/// The permissions of the sockets decide who may connect.
+	pub unix: bool
+}
+
+impl TrustedProxies {
+	/// Return whether ``addr`` is the address of a trusted proxy.
+	pub fn contains(&self, addr: &ClientAddr) -> bool {
+		match addr {
+			ClientAddr::Inet(addr) => {
+				let ip = addr.ip().to_canonical();
+				self.addrs.iter().any(|trusted| trusted.to_canonical() == ip)
+			},
+			#[cfg(unix)]
+			ClientAddr::Unix(_) => self.unix
+		}
+	}
+}
+
+// A connection from a trusted proxy. The PROXY protocol header is read along
+// with the first data read from the connection, so accepting it doesn't wait
+// for the proxy.
+pub(crate) struct ProxiedStream {
+	inner: Box<dyn Transport>,
+	// The header while it is incomplete, followed by what was read after it
+	buffer: Vec<u8>,
+	// How much of the buffer has been read past the header
+	position: usize,
+	has_header: bool,
+	// The address the proxy accepted the connection from, until it is taken
+	source: Option<ClientAddr>
+}
+
+impl ProxiedStream {
+	pub(crate) fn new(inner: Box<dyn Transport>) -> Self {
+		ProxiedStream {
+			inner,
+			buffer: Vec::new(),
+			position: 0,
+			has_header: false,
+			source: None
+		}
+	}
+
+	fn read_header(&mut self) -> io::Result<()> {
+		let mut chunk = [0; 512];
+		loop {
+			if let Some((length, source)) = parse_header(&self.buffer)? {
+				self.position = length;
+				self.source = source;
+				self.has_header = true;
+				return Ok(());
+			}
+			match self.inner.read(&mut chunk)? {
+				// A connection closed without sending anything is left for
+				// the client to notice, e.g. a health check
+				0 if self.buffer.is_empty() => {
+					self.has_header = true;
+					return Ok(());
+				},
+				0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+				bytes_read => self.buffer.extend_from_slice(&chunk[..bytes_read])
+			}
+		}
+	}
+}
+
+impl Read for ProxiedStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if !self.has_header {
+			self.read_header()?;
+		}
+		if self.position < self.buffer.len() {
+			let bytes_read = (&self.buffer[self.position..]).read(buf)?;
+			self.position += bytes_read;
+			return Ok(bytes_read);
+		}
+		self.inner.read(buf)
+	}
+}
+
+impl Write for ProxiedStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.inner.write(buf)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+impl Transport for ProxiedStream {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.inner.set_read_timeout(timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.inner.set_write_timeout(timeout)
+	}
+
+	fn shutdown_write(&mut self) -> io::Result<()> {
+		self.inner.shutdown_write()
+	}
+
+	fn take_source(&mut self) -> Option<ClientAddr> {
+		self.source.take()
+	}
+
+	#[cfg(all(feature = "event-loop", unix))]
+	fn socket(&self) -> Option<std::os::unix::io::RawFd> {
+		self.inner.socket()
+	}
+
+	#[cfg(all(feature = "event-loop", unix))]
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		self.inner.set_nonblocking(nonblocking)
+	}
+
+	#[cfg(all(feature = "event-loop", unix))]
+	fn has_buffered_input(&mut self) -> bool {
+		(self.has_header && self.position < self.buffer.len()) || self.inner.has_buffered_input()
+	}
+}
+
+impl fmt::Debug for ProxiedStream {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("ProxiedStream").field(&self.inner).finish()
+	}
+}
+
+fn invalid_header() -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, "Invalid PROXY protocol header")
+}
+
+// Parse the header at the start of ``buffer``, returning its length and the
+// source address it names, or ``None`` if more data is needed. The address
+// is ``None`` as well if the proxy doesn't know it, e.g. for its own health
+// checks, in which case the proxy's address is kept.
+fn parse_header(buffer: &[u8]) -> io::Result<Option<(usize, Option<ClientAddr>)>> {
+	let starts_with = |signature: &[u8]| {
+		let length = buffer.len().min(signature.len());
+		buffer[..length] == signature[..length]
+	};
+	if starts_with(V1_SIGNATURE) {
+		parse_v1(buffer)
+	} else if starts_with(V2_SIGNATURE) {
+		parse_v2(buffer)
+	} else {
+		Err(invalid_header())
+	}
+}
+
+// A line like "PROXY TCP4 1.2.3.4 5.6.7.8 9435 443\r\n"
+fn parse_v1(buffer: &[u8]) -> io::Result<Option<(usize, Option<ClientAddr>)>> {
+	let length = match buffer.iter().take(V1_MAX_LENGTH).position(|&b| b == b'\n') {
+		Some(i) => i + 1,
+		None if buffer.len() < V1_MAX_LENGTH => return Ok(None),
+		None => return Err(invalid_header())
+	};
+	let line = buffer[..length].strip_suffix(b"\r\n").ok_or_else(invalid_header)?;
+	let line = str::from_utf8(line).map_err(|_| invalid_header())?;
+
+	let fields: Vec<&str> = line.split(' ').collect();
+	let source = match fields[..] {
+		["PROXY", "UNKNOWN", ..] => None,
+		["PROXY", protocol, source, _, port, _] => {
+			let ip: IpAddr = source.parse().map_err(|_| invalid_header())?;
+			let port: u16 = port.parse().map_err(|_| invalid_header())?;
+			match (protocol, ip) {
+				("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) => Some(ClientAddr::Inet(SocketAddr::new(ip, port))),
+				_ => return Err(invalid_header())
+			}
+		},
+		_ => return Err(invalid_header())
+	};
+	Ok(Some((length, source)))
+}
+
+// The signature, the version and command, the address family and protocol,
+// and the length of the addresses which follow
+fn parse_v2(buffer: &[u8]) -> io::Result<Option<(usize, Option<ClientAddr>)>> {
+	if buffer.len() < V2_FIXED_LENGTH {
+		return Ok(None);
+	}
+	let length = V2_FIXED_LENGTH + usize::from(u16::from_be_bytes([buffer[14], buffer[15]]));
+	if buffer.len() < length {
+		return Ok(None);
+	}
+	let addrs = &buffer[V2_FIXED_LENGTH..length];
+	let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+
+	let source = match buffer[12] {
+		// LOCAL, a connection the proxy made by itself
+		0x20 => None,
+		// PROXY, over TCP with IPv4 or IPv6, or anything else we don't use
+		0x21 => match buffer[13] {
+			0x11 if addrs.len() >= 12 => {
+				let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addrs[..4]).unwrap());
+				Some(ClientAddr::Inet(SocketAddr::new(ip.into(), port(8))))
+			},
+			0x21 if addrs.len() >= 36 => {
+				let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addrs[..16]).unwrap());
+				Some(ClientAddr::Inet(SocketAddr::new(ip.into(), port(32))))
+			},
+			0x11 | 0x21 => return Err(invalid_header()),
+			_ => None
+		},
+		_ => return Err(invalid_header())
+	};
+	Ok(Some((length, source)))
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use urlencoding::{decode, encode};
+
+/// Key-value pairs encoded like a query string (``a=1&b=2``), as found in
+/// request URLs and URL encoded forms.
+///
+/// Pairs keep the order they were sent in, and a key may occur several times
+/// (``tag=a&tag=b``). Keys and values are percent-decoded, and ``+`` is
+/// decoded as a space.
+///
+/// Formatting with ``Display`` encodes the pairs as a query string again.
+///
+/// # Example
+///
+/// ```
+/// use micro_http_server::QueryData;
+///
+/// let query = QueryData::parse("tag=a&tag=b&q=hello+world%21");
+/// assert_eq!(Some("a"), query.get("tag"));
+/// assert_eq!(vec!["a", "b"], query.get_all("tag").collect::<Vec<_>>());
+/// assert_eq!(Some("hello world!"), query.get("q"));
+/// assert_eq!("tag=a&tag=b&q=hello%20world%21", query.to_string());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryData {
+	pairs: Vec<(String, String)>
+}
+
+impl QueryData {
+	/// Create empty query data.
+	pub fn new() -> Self {
+		QueryData::default()
+	}
+
+	/// Parse a query string, without the leading ``?``. Pairs which can't be
+	/// decoded (e.g. because they are not valid UTF-8) are skipped, as are
+	/// empty ones.
+	pub fn parse(s: &str) -> Self {
+		let pairs = s.trim().split('&')
+			.filter(|pair| !pair.is_empty())
+			.filter_map(|pair| {
+				let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+				Some((decode_component(k)?, decode_component(v)?))
+			})
+			.collect();
+		QueryData { pairs }
+	}
+
+	/// Return the value of the first pair with the given key.
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.pairs.iter()
+			.find(|(k, _)| k == key)
+			.map(|(_, v)| v.as_str())
+	}
+
+	/// Return the values of all pairs with the given key, in order.
+	pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+		self.pairs.iter()
+			.filter(move |(k, _)| k == key)
+			.map(|(_, v)| v.as_str())
+	}
+
+	/// Whether there is a pair with the given key.
+	pub fn contains_key(&self, key: &str) -> bool {
+		self.get(key).is_some()
+	}
+
+	/// Add a pair, keeping any pairs with the same key.
+	pub fn append(&mut self, key: impl Into<String>, value: impl Into<String>) {
+		self.pairs.push((key.into(), value.into()));
+	}
+
+	/// Remove all pairs with the given key.
+	pub fn remove(&mut self, key: &str) {
+		self.pairs.retain(|(k, _)| k != key);
+	}
+
+	/// Return all pairs in order.
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+		self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+	}
+
+	/// Return the number of pairs.
+	pub fn len(&self) -> usize {
+		self.pairs.len()
+	}
+
+	/// Whether there are no pairs.
+	pub fn is_empty(&self) -> bool {
+		self.pairs.is_empty()
+	}
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for QueryData {
+	fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+		QueryData {
+			pairs: iter.into_iter().map(|(k, v)| (k.into(), v.into())).collect()
+		}
+	}
+}
+
+impl fmt::Display for QueryData {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (i, (k, v)) in self.pairs.iter().enumerate() {
+			if i > 0 {
+				write!(f, "&")?;
+			}
+			write!(f, "{}={}", encode(k), encode(v))?;
+		}
+		Ok(())
+	}
+}
+
+// Decode a key or value of a query string
+fn decode_component(s: &str) -> Option<String> {
+	decode(&s.replace('+', " ")).ok().map(|s| s.into_owned())
+}
@@ -0,0 +1,151 @@
+use std::{
+	borrow::Cow,
+	fmt,
+	io::Read
+};
+use crate::header_map::HeaderMap;
+use crate::status::StatusCode;
+
+/// The body of a ``Response``.
+pub enum ResponseBody<'a> {
+	/// No body, sent with ``Content-Length: 0``
+	Empty,
+	/// A body which is completely in memory
+	Bytes(Cow<'a, [u8]>),
+	/// A body which is read while it is being sent, whose length is known
+	/// in advance
+	Reader(Box<dyn Read + 'a>, usize),
+	/// A body which is read while it is being sent, whose length is not known
+	/// in advance. It is sent with ``Transfer-Encoding: chunked`` to HTTP/1.1
+	/// clients; for HTTP/1.0 clients, the connection is closed afterwards.
+	Stream(Box<dyn Read + 'a>),
+	/// A body read from local files, with its length if it is known in
+	/// advance. See ``Response::body_file``.
+	File(Box<dyn Read + Send>, Option<usize>)
+}
+
+impl fmt::Debug for ResponseBody<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ResponseBody::Empty => write!(f, "Empty"),
+			ResponseBody::Bytes(data) => write!(f, "Bytes({} bytes)", data.len()),
+			ResponseBody::Reader(_, length) => write!(f, "Reader({} bytes)", length),
+			ResponseBody::Stream(_) => write!(f, "Stream"),
+			ResponseBody::File(_, Some(length)) => write!(f, "File({} bytes)", length),
+			ResponseBody::File(_, None) => write!(f, "File")
+		}
+	}
+}
+
+/// A response to be sent with ``Client::send``.
+///
+/// The headers which determine how the body is framed (``Content-Length`` and
+/// ``Transfer-Encoding``) are derived from the body, and ``Connection`` from
+/// the state of the connection. ``Date`` and ``Server`` are added unless the
+/// response already has them.
+///
+/// # Example
+///
+/// ```
+/// use micro_http_server::{Response, StatusCode};
+///
+/// let response = Response::new(StatusCode::NotFound)
+///     .header("Cache-Control", "no-cache")
+///     .body("Nothing here.".as_bytes());
+/// assert_eq!(StatusCode::NotFound, response.status());
+/// assert_eq!(Some("no-cache"), response.headers().get("cache-control"));
+/// ```
+#[derive(Debug)]
+pub struct Response<'a> {
+	status: StatusCode,
+	headers: HeaderMap,
+	body: ResponseBody<'a>
+}
+
+impl<'a> Response<'a> {
+	/// Create a response with the given status, no headers and an empty body.
+	pub fn new(status: StatusCode) -> Self {
+		Response {
+			status,
+			headers: HeaderMap::new(),
+			body: ResponseBody::Empty
+		}
+	}
+
+	/// Create a ``200 OK`` response with no headers and an empty body.
+	pub fn ok() -> Self {
+		Response::new(StatusCode::Ok)
+	}
+
+	/// Set a header, replacing any header with the same name.
+	pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+		self.headers.insert(name, value);
+		self
+	}
+
+	/// Add all headers of the given map, keeping any headers with the same name.
+	pub fn headers_from(mut self, headers: HeaderMap) -> Self {
+		self.headers.extend(headers.iter());
+		self
+	}
+
+	/// Set the body to data in memory.
+	pub fn body(mut self, data: impl Into<Cow<'a, [u8]>>) -> Self {
+		self.body = ResponseBody::Bytes(data.into());
+		self
+	}
+
+	/// Set the body to data read from ``reader``, which yields exactly
+	/// ``length`` bytes.
+	///
+	/// If the reader yields a different number of bytes, the client can't tell
+	/// where the response ends, so the connection is closed afterwards.
+	pub fn body_reader(mut self, reader: impl Read + 'a, length: usize) -> Self {
+		self.body = ResponseBody::Reader(Box::new(reader), length);
+		self
+	}
+
+	/// Set the body to data read from ``reader``, whose length is not known
+	/// in advance, e.g. the output of a process.
+	pub fn body_stream(mut self, reader: impl Read + 'a) -> Self {
+		self.body = ResponseBody::Stream(Box::new(reader));
+		self
+	}
+
+	/// Set the body to data read from local files, e.g. a ``std::fs::File``,
+	/// with its length if it is known in advance (see ``body_reader`` and
+	/// ``body_stream``).
+	///
+	/// For clients served by an ``EventLoop``, the body is sent by the event
+	/// loop after the handler gave the client back, so a slow client doesn't
+	/// hold up the handler's thread. Reading must therefore not wait for
+	/// anything but the disk, unlike e.g. reading the output of a process.
+	pub fn body_file(mut self, reader: impl Read + Send + 'static, length: Option<usize>) -> Self {
+		self.body = ResponseBody::File(Box::new(reader), length);
+		self
+	}
+
+	/// Return the status of the response.
+	pub fn status(&self) -> StatusCode {
+		self.status
+	}
+
+	/// Change the status of the response.
+	pub fn set_status(&mut self, status: StatusCode) {
+		self.status = status;
+	}
+
+	/// Return the headers of the response.
+	pub fn headers(&self) -> &HeaderMap {
+		&self.headers
+	}
+
+	/// Return the headers of the response for modification.
+	pub fn headers_mut(&mut self) -> &mut HeaderMap {
+		&mut self.headers
+	}
+
+	pub(crate) fn into_parts(self) -> (StatusCode, HeaderMap, ResponseBody<'a>) {
+		(self.status, self.headers, self.body)
+	}
+}
@@ -0,0 +1,144 @@
+use std::{fmt, io, str::FromStr};
+
+macro_rules! status_codes {
+	($($(#[$doc:meta])* $name:ident = $code:expr, $reason:expr;)*) => {
+		/// The status code of a response, e.g. ``StatusCode::NotFound`` for
+		/// ``404 Not Found``.
+		///
+		/// Formatting a status code with ``Display`` gives the code followed by
+		/// its reason phrase, as it appears in the status line of a response.
+		#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+		pub enum StatusCode {
+			$($(#[$doc])* $name,)*
+		}
+
+		impl StatusCode {
+			/// Return the numeric code, e.g. ``404``.
+			pub fn code(self) -> u16 {
+				match self {
+					$(StatusCode::$name => $code,)*
+				}
+			}
+
+			/// Return the reason phrase, e.g. ``Not Found``.
+			pub fn reason(self) -> &'static str {
+				match self {
+					$(StatusCode::$name => $reason,)*
+				}
+			}
+
+			/// Return the status code with the given numeric code, if it is known.
+			pub fn from_u16(code: u16) -> Option<StatusCode> {
+				match code {
+					$($code => Some(StatusCode::$name),)*
+					_ => None
+				}
+			}
+		}
+	}
+}
+
+status_codes! {
+	/// 100 Continue
+	Continue = 100, "Continue";
+	/// 101 Switching Protocols
+	SwitchingProtocols = 101, "Switching Protocols";
+	/// 200 OK
+	Ok = 200, "OK";
+	/// 201 Created
+	Created = 201, "Created";
+	/// 202 Accepted
+	Accepted = 202, "Accepted";
+	/// 204 No Content
+	NoContent = 204, "No Content";
+	/// 206 Partial Content
+	PartialContent = 206, "Partial Content";
+	/// 301 Moved Permanently
+	MovedPermanently = 301, "Moved Permanently";
+	/// 302 Found
+	Found = 302, "Found";
+	/// 303 See Other
+	SeeOther = 303, "See Other";
+	/// 304 Not Modified
+	NotModified = 304, "Not Modified";
+	/// 307 Temporary Redirect
+	TemporaryRedirect = 307, "Temporary Redirect";
+	/// 308 Permanent Redirect
+	PermanentRedirect = 308, "Permanent Redirect";
+	/// 400 Bad Request
+	BadRequest = 400, "Bad Request";
+	/// 401 Unauthorized
+	Unauthorized = 401, "Unauthorized";
+	/// 403 Forbidden
+	Forbidden = 403, "Forbidden";
+	/// 404 Not Found
+	NotFound = 404, "Not Found";
+	/// 405 Method Not Allowed
+	MethodNotAllowed = 405, "Method Not Allowed";
+	/// 406 Not Acceptable
+	NotAcceptable = 406, "Not Acceptable";
+	/// 408 Request Timeout
+	RequestTimeout = 408, "Request Timeout";
+	/// 409 Conflict
+	Conflict = 409, "Conflict";
+	/// 410 Gone
+	Gone = 410, "Gone";
+	/// 411 Length Required
+	LengthRequired = 411, "Length Required";
+	/// 412 Precondition Failed
+	PreconditionFailed = 412, "Precondition Failed";
+	/// 413 Payload Too Large
+	PayloadTooLarge = 413, "Payload Too Large";
+	/// 414 URI Too Long
+	URITooLong = 414, "URI Too Long";
+	/// 415 Unsupported Media Type
+	UnsupportedMediaType = 415, "Unsupported Media Type";
+	/// 416 Range Not Satisfiable
+	RangeNotSatisfiable = 416, "Range Not Satisfiable";
+	/// 417 Expectation Failed
+	ExpectationFailed = 417, "Expectation Failed";
+	/// 429 Too Many Requests
+	TooManyRequests = 429, "Too Many Requests";
+	/// 431 Request Header Fields Too Large
+	RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+	/// 500 Internal Server Error
+	InternalServerError = 500, "Internal Server Error";
+	/// 501 Not Implemented
+	NotImplemented = 501, "Not Implemented";
+	/// 502 Bad Gateway
+	BadGateway = 502, "Bad Gateway";
+	/// 503 Service Unavailable
+	ServiceUnavailable = 503, "Service Unavailable";
+	/// 504 Gateway Timeout
+	GatewayTimeout = 504, "Gateway Timeout";
+	/// 505 HTTP Version Not Supported
+	HTTPVersionNotSupported = 505, "HTTP Version Not Supported";
+}
+
+impl StatusCode {
+	/// Whether responses with this status never have a body (``1xx``,
+	/// ``204 No Content`` and ``304 Not Modified``).
+	pub fn forbids_body(self) -> bool {
+		self.code() < 200 || self == StatusCode::NoContent || self == StatusCode::NotModified
+	}
+}
+
+impl fmt::Display for StatusCode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} {}", self.code(), self.reason())
+	}
+}
+
+/// Parse a status code from a string like ``404`` or ``404 Not Found``. The
+/// reason phrase is ignored.
+impl FromStr for StatusCode {
+	type Err = io::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let code = s.split_whitespace().next().unwrap_or("");
+		code.parse().ok()
+			.and_then(StatusCode::from_u16)
+			.ok_or_else(|| io::Error::new(
+				io::ErrorKind::InvalidInput, format!("Unknown status code: {}", s)))
+	}
+}
@@ -0,0 +1,61 @@
+use std::{
+	io,
+	sync::{Arc, atomic::{AtomicBool, Ordering}}
+};
+#[cfg(unix)]
+use std::{
+	io::Write,
+	os::unix::{io::{AsRawFd, RawFd}, net::UnixStream}
+};
+
+/// Stops a server from accepting connections, e.g. when the process is asked
+/// to shut down. See ``MicroHTTP::stop_handle``.
+///
+/// Clients which were already accepted are not affected.
+#[derive(Debug, Clone)]
+pub struct StopHandle(pub(crate) Arc<StopState>);
+
+impl StopHandle {
+	/// Stop the server. Threads waiting in ``MicroHTTP::next_client`` return
+	/// right away, and so do later calls.
+	pub fn stop(&self) {
+		self.0.stopped.store(true, Ordering::SeqCst);
+		// Whatever is written stays unread, so the socket stays readable
+		// and wakes every thread waiting for it
+		#[cfg(unix)]
+		drop((&self.0.wake.1).write(&[1]));
+	}
+}
+
+#[derive(Debug)]
+pub(crate) struct StopState {
+	stopped: AtomicBool,
+	// Becomes readable when the server is stopped
+	#[cfg(unix)]
+	wake: (UnixStream, UnixStream)
+}
+
+impl StopState {
+	pub(crate) fn new() -> io::Result<StopState> {
+		#[cfg(unix)]
+		let wake = UnixStream::pair()?;
+		#[cfg(unix)]
+		wake.1.set_nonblocking(true)?;
+
+		Ok(StopState {
+			stopped: AtomicBool::new(false),
+			#[cfg(unix)]
+			wake
+		})
+	}
+
+	pub(crate) fn is_stopped(&self) -> bool {
+		self.stopped.load(Ordering::SeqCst)
+	}
+
+	// A file descriptor which becomes readable when the server is stopped
+	#[cfg(unix)]
+	pub(crate) fn wake_fd(&self) -> RawFd {
+		self.wake.0.as_raw_fd()
+	}
+}
@@ -0,0 +1,187 @@
+use std::{
+	fmt,
+	fs::File,
+	io::{self, BufReader, Read, Write},
+	path::{Path, PathBuf},
+	sync::{Arc, RwLock},
+	time::Duration
+};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use crate::{listener::ClientAddr, transport::Transport};
+
+/// The certificate and private key a server uses to accept TLS connections,
+/// loaded from PEM files. Requires the ``tls`` feature.
+///
+/// Clones share the certificate, so after ``reload``, every server the
+/// configuration was given to uses the new one.
+///
+/// # Example
+///
+/// ```no_run
+/// use micro_http_server::{MicroHTTP, TlsConfig};
+///
+/// let tls = TlsConfig::from_pem_files("/etc/ssl/cert.pem", "/etc/ssl/key.pem")
+///     .expect("Could not load certificate");
+/// let mut server = MicroHTTP::new("0.0.0.0:443").expect("Could not create server.");
+/// server.set_tls(Some(tls.clone()));
+///
+/// // Later, e.g. after the certificate was renewed
+/// tls.reload().expect("Could not reload certificate");
+/// ```
+#[derive(Clone)]
+pub struct TlsConfig {
+	cert_path: PathBuf,
+	key_path: PathBuf,
+	config: Arc<RwLock<Arc<ServerConfig>>>
+}
+
+impl TlsConfig {
+	/// Load a certificate chain (the server's certificate first) and the
+	/// matching private key from PEM files.
+	pub fn from_pem_files(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> io::Result<TlsConfig> {
+		let cert_path = cert_path.into();
+		let key_path = key_path.into();
+		let config = load_config(&cert_path, &key_path)?;
+
+		Ok(TlsConfig {
+			cert_path,
+			key_path,
+			config: Arc::new(RwLock::new(config))
+		})
+	}
+
+	/// Load the certificate and key again from the same files, e.g. after
+	/// they were renewed. Connections which are already open are not affected.
+	///
+	/// If loading fails, the previous certificate and key stay in use.
+	pub fn reload(&self) -> io::Result<()> {
+		let config = load_config(&self.cert_path, &self.key_path)?;
+		*self.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+		Ok(())
+	}
+
+	// Start a TLS connection on an accepted stream. The handshake happens
+	// when the stream is first read from or written to.
+	pub(crate) fn accept(&self, stream: Box<dyn Transport>) -> io::Result<TlsStream> {
+		let config = self.config.read().unwrap_or_else(|e| e.into_inner()).clone();
+		let connection = ServerConnection::new(config).map_err(invalid_data)?;
+		Ok(TlsStream(StreamOwned::new(connection, stream)))
+	}
+}
+
+impl fmt::Debug for TlsConfig {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("TlsConfig")
+			.field("cert_path", &self.cert_path)
+			.field("key_path", &self.key_path)
+			.finish()
+	}
+}
+
+fn load_config(cert_path: &Path, key_path: &Path) -> io::Result<Arc<ServerConfig>> {
+	let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+		.collect::<Result<Vec<_>, _>>()?;
+	if certs.is_empty() {
+		return Err(invalid_data(format!("No certificate found in {}", cert_path.display())));
+	}
+	let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+		.ok_or_else(|| invalid_data(format!("No private key found in {}", key_path.display())))?;
+
+	let provider = Arc::new(rustls::crypto::ring::default_provider());
+	let mut config = ServerConfig::builder_with_provider(provider)
+		.with_safe_default_protocol_versions()
+		.map_err(invalid_data)?
+		.with_no_client_auth()
+		.with_single_cert(certs, key)
+		.map_err(invalid_data)?;
+	config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+	Ok(Arc::new(config))
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// A TLS connection to a client
+pub(crate) struct TlsStream(StreamOwned<ServerConnection, Box<dyn Transport>>);
+
+impl Read for TlsStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.0.read(buf)
+	}
+}
+
+impl Write for TlsStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.write(buf)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.0.flush()
+	}
+}
+
+impl Transport for TlsStream {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.0.sock.set_read_timeout(timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		self.0.sock.set_write_timeout(timeout)
+	}
+
+	fn shutdown_write(&mut self) -> io::Result<()> {
+		self.send_close_notify();
+		self.0.sock.shutdown_write()
+	}
+
+	fn take_source(&mut self) -> Option<ClientAddr> {
+		self.0.sock.take_source()
+	}
+
+	#[cfg(all(feature = "event-loop", unix))]
+	fn socket(&self) -> Option<std::os::unix::io::RawFd> {
+		self.0.sock.socket()
+	}
+
+	#[cfg(all(feature = "event-loop", unix))]
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		self.0.sock.set_nonblocking(nonblocking)
+	}
+
+	// Records which were received but not read yet may hold a request. If
+	// they are invalid, reading reports it.
+	#[cfg(all(feature = "event-loop", unix))]
+	fn has_buffered_input(&mut self) -> bool {
+		self.0.conn.process_new_packets()
+			.map(|state| state.plaintext_bytes_to_read() > 0 || state.peer_has_closed())
+			.unwrap_or(true)
+	}
+}
+
+impl fmt::Debug for TlsStream {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("TlsStream").field(&self.0.sock).finish()
+	}
+}
+
+impl Drop for TlsStream {
+	// Tell the client that the connection is closed on purpose, so it can
+	// tell a complete response from a truncated one.
+	fn drop(&mut self) {
+		self.send_close_notify();
+	}
+}
+
+impl TlsStream {
+	fn send_close_notify(&mut self) {
+		self.0.conn.send_close_notify();
+		while self.0.conn.wants_write() {
+			match self.0.conn.write_tls(&mut self.0.sock) {
+				Ok(0) | Err(_) => break,
+				Ok(_) => {}
+			}
+		}
+	}
+}
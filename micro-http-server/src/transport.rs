@@ -0,0 +1,131 @@
+use std::{
+	fmt,
+	io::{self, Read, Write},
+	net::{Shutdown, TcpStream},
+	time::Duration
+};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use crate::listener::ClientAddr;
+#[cfg(all(feature = "event-loop", unix))]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+// A connection to a client, over which requests are read and responses are
+// written.
+pub(crate) trait Transport: Read + Write + Send + fmt::Debug {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+	// Stop sending, while the client may still send something
+	fn shutdown_write(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+
+	// The address a proxy accepted the connection from, once it is known.
+	// It is only returned once.
+	fn take_source(&mut self) -> Option<ClientAddr> {
+		None
+	}
+
+	// The socket an event loop waits for, if there is one
+	#[cfg(all(feature = "event-loop", unix))]
+	fn socket(&self) -> Option<RawFd> {
+		None
+	}
+
+	#[cfg(all(feature = "event-loop", unix))]
+	fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+		Ok(())
+	}
+
+	// Whether data was received which can be read without the socket
+	// becoming readable again, e.g. because it was already decrypted
+	#[cfg(all(feature = "event-loop", unix))]
+	fn has_buffered_input(&mut self) -> bool {
+		false
+	}
+}
+
+impl Transport for TcpStream {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		TcpStream::set_read_timeout(self, timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		TcpStream::set_write_timeout(self, timeout)
+	}
+
+	fn shutdown_write(&mut self) -> io::Result<()> {
+		TcpStream::shutdown(self, Shutdown::Write)
+	}
+
+	#[cfg(all(feature = "event-loop", unix))]
+	fn socket(&self) -> Option<RawFd> {
+		Some(self.as_raw_fd())
+	}
+
+	#[cfg(all(feature = "event-loop", unix))]
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		TcpStream::set_nonblocking(self, nonblocking)
+	}
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+	fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		UnixStream::set_read_timeout(self, timeout)
+	}
+
+	fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+		UnixStream::set_write_timeout(self, timeout)
+	}
+
+	fn shutdown_write(&mut self) -> io::Result<()> {
+		UnixStream::shutdown(self, Shutdown::Write)
+	}
+
+	#[cfg(feature = "event-loop")]
+	fn socket(&self) -> Option<RawFd> {
+		Some(self.as_raw_fd())
+	}
+
+	#[cfg(feature = "event-loop")]
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		UnixStream::set_nonblocking(self, nonblocking)
+	}
+}
+
+// A stream given to ``Client::from_stream``, which has no timeouts
+pub(crate) struct Untimed<S>(pub(crate) S);
+
+impl<S: Read> Read for Untimed<S> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.0.read(buf)
+	}
+}
+
+impl<S: Write> Write for Untimed<S> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.write(buf)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.0.flush()
+	}
+}
+
+impl<S: Read + Write + Send> Transport for Untimed<S> {
+	fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+		Ok(())
+	}
+
+	fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl<S> fmt::Debug for Untimed<S> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("Untimed")
+	}
+}
@@ -3,6 +3,7 @@ use std::fs::DirEntry;
 use std::io::{Result, Read};
 use std::cmp::{self, Ordering};
 use crate::file_reader::FileReader;
+use crate::compression;
 use chrono::{DateTime, Local};
 use urlencoding::encode;
 
@@ -10,16 +11,15 @@ fn is_special_file_name<S>(file_name: S) -> bool
 where S: AsRef<str> {
     let file_name = file_name.as_ref();
 
-    match file_name {
-        "header.html" | "footer.html" | "styles.css" | "title" => true,
-        _ => false
-    }
+    matches!(file_name, "header.html" | "footer.html" | "styles.css" | "title")
 }
 
-pub fn generate_index<F: 'static>(
-    path: impl AsRef<Path>, header: Option<&str>, f: F,
+// Generate the index of a directory, whose path in URLs (as the client
+// reaches it, ending with "/") is `url_path`
+pub fn generate_index<F>(
+    path: impl AsRef<Path>, url_path: &str, header: Option<&str>, f: F,
     page_size: usize, page_number: usize) -> Result<String>
-where F: Fn(Result<DirEntry>) -> Option<DirEntry> {
+where F: Fn(Result<DirEntry>) -> Option<DirEntry> + 'static {
     let path = path.as_ref();
     let mut entries: Vec<DirEntry> = path
         .read_dir()?
@@ -27,6 +27,7 @@ where F: Fn(Result<DirEntry>) -> Option<DirEntry> {
         .filter(|file| {
             file.metadata().is_ok() && file.metadata().unwrap().modified().is_ok()
             && !is_special_file_name(file.file_name().to_string_lossy())
+            && !compression::is_precompressed(&file.path())
         })
         .collect();
     // Sort entries (Directories first, then files) where each group is sorted
@@ -42,7 +43,7 @@ where F: Fn(Result<DirEntry>) -> Option<DirEntry> {
     });
 
     // Skip the "/home/user/www" and just display the rest of the path
-    let display_path = format!("{}", path.components().skip(4).fold(PathBuf::new(), |mut p, e| { p.push(e); p }).to_str().unwrap_or(""));
+    let display_path = path.components().skip(4).fold(PathBuf::new(), |mut p, e| { p.push(e); p }).to_str().unwrap_or("").to_owned();
 
     let title = if let Some(head) = header {
         head.to_owned()
@@ -52,7 +53,11 @@ where F: Fn(Result<DirEntry>) -> Option<DirEntry> {
         display_path.to_owned()
     };
 
-    let head = format!("<title>{}</title>\n<link rel=\"stylesheet\" href=\"styles.css\"/>\n<base href=\"./\"/>", title);
+    let head = format!("<title>{}</title>\n<link rel=\"stylesheet\" href=\"styles.css\"/>\n<base href=\"{}\"/>", title, url_path);
+
+    // The index of all users is where the server's URLs start, so there is
+    // nothing above it
+    let has_parent = path != Path::new("/home");
 
     // Set the page heading
     let header = if let Some(header) = header {
@@ -70,8 +75,10 @@ where F: Fn(Result<DirEntry>) -> Option<DirEntry> {
         // No pagination
         body.push_str("
             <ol class=\"entries\">");
-        body.push_str("
+        if has_parent {
+            body.push_str("
             <a href=\"../\">../<br/></a>");
+        }
 
         for entry in entries {
             body.push_str(&format_entry(&entry));
@@ -81,18 +88,20 @@ where F: Fn(Result<DirEntry>) -> Option<DirEntry> {
             </ol>");
     } else {
         // Pagination
-        let num_pages = (entries.len() + page_size - 1) / page_size;
+        let num_pages = entries.len().div_ceil(page_size);
         let last_index = entries.len() - 1;
         let start = cmp::min(page_number * page_size, last_index);
         let end = cmp::min(start + page_size - 1, last_index);
 
         body.push_str(&format!("
             <ol class=\"entries\" start=\"{}\">", start + 1));
-        body.push_str("
+        if has_parent {
+            body.push_str("
             <a href=\"../\">../</a>");
+        }
 
         for entry in &entries[start..=end] {
-            body.push_str(&format_entry(&entry));
+            body.push_str(&format_entry(entry));
         }
 
         body.push_str("
@@ -152,6 +161,6 @@ fn read_file(file_path: PathBuf) -> Option<String> {
     let mut s = String::new();
     let mut reader = FileReader::new(file_path).ok()?;
     reader.read_to_string(&mut s).ok()?;
-    s.push_str("\n");
+    s.push('\n');
     Some(s)
 }
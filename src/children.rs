@@ -0,0 +1,77 @@
+// Executables run for requests, which are terminated if they are still
+// running when the server shuts down
+
+use std::io::{self, Result};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::{Mutex, MutexGuard};
+
+
+// Process IDs of the executables which haven't been waited for yet
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+fn running() -> MutexGuard<'static, Vec<u32>> {
+    RUNNING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+
+// A running executable. It is waited for when dropped, so it doesn't linger
+// as a zombie process.
+pub struct RunningChild(Child);
+
+impl RunningChild {
+    pub fn spawn(command: &mut Command) -> Result<Self> {
+        let mut running = running();
+        let child = command.spawn()?;
+        running.push(child.id());
+        Ok(RunningChild(child))
+    }
+
+    pub fn wait(&mut self) -> Result<ExitStatus> {
+        // Wait for the process to exit without reaping it, so its process ID
+        // can't be taken by another process while it is still registered
+        let pid = self.0.id();
+        loop {
+            let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+            let result = unsafe {
+                libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT)
+            };
+            if result == 0 || io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                break;
+            }
+        }
+        running().retain(|&p| p != pid);
+        self.0.wait()
+    }
+}
+
+impl Deref for RunningChild {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        &self.0
+    }
+}
+
+impl DerefMut for RunningChild {
+    fn deref_mut(&mut self) -> &mut Child {
+        &mut self.0
+    }
+}
+
+impl Drop for RunningChild {
+    fn drop(&mut self) {
+        drop(self.wait());
+    }
+}
+
+
+// Ask all executables which are still running to terminate
+pub fn terminate_all() {
+    for &pid in running().iter() {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+    }
+}
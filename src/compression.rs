@@ -0,0 +1,368 @@
+// Compression of responses with gzip, Brotli or Zstandard, whichever the
+// client prefers of those it accepts in the Accept-Encoding header. Bodies are
+// compressed while they are sent, so files and the output of executables
+// don't have to be read completely first. Files may also be compressed in
+// advance, like "style.css.br" next to "style.css", which is then sent instead.
+
+use std::fs::{self, Metadata};
+use std::io::{Read, Result, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use brotli::CompressorWriter;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use micro_http_server::{Headers, Request, Response};
+use crate::mime;
+
+
+// Smaller bodies hardly get smaller, but take as long to compress
+const MIN_SIZE: usize = 1024;
+// How much of a body is compressed at a time
+const CHUNK_SIZE: usize = 16 * 1024;
+
+// Levels which compress well enough while the client waits
+const GZIP_LEVEL: u32 = 6;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const ZSTD_LEVEL: i32 = 3;
+
+// Types which are not text but compress well
+const COMPRESSIBLE: &[&str] = &[
+    "application/wasm",
+    "image/bmp",
+    "image/vnd.microsoft.icon",
+    "font/ttf",
+    "font/otf"
+];
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip
+}
+
+impl Encoding {
+    // In order of preference, if the client accepts several equally
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    // Those files may be compressed with in advance
+    const PRECOMPRESSED: [Encoding; 2] = [Encoding::Brotli, Encoding::Gzip];
+
+    // The name in Accept-Encoding and Content-Encoding
+    pub fn token(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip"
+        }
+    }
+
+    // The extension of files compressed in advance
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zst",
+            Encoding::Gzip => "gz"
+        }
+    }
+}
+
+
+// How the body of a response is sent, as negotiated with the client
+#[derive(Debug)]
+pub struct Negotiated {
+    // What the body is compressed with, if anything
+    pub encoding: Option<Encoding>,
+    // Whether the response depends on Accept-Encoding, i.e. other clients
+    // may get it compressed differently
+    pub varies: bool,
+    // The file compressed in advance to send instead of compressing the
+    // requested one, and its metadata
+    pub precompressed: Option<(PathBuf, Metadata)>
+}
+
+impl Negotiated {
+    // Negotiate for a body of the given media type and size, either of
+    // which may not be known in advance
+    pub fn new(request: &Request, content_type: Option<&str>, size: Option<usize>) -> Self {
+        let varies = content_type.is_none_or(is_compressible) && size.is_none_or(|size| size >= MIN_SIZE);
+        Self {
+            encoding: if varies { accepted(request.headers(), |_| true) } else { None },
+            varies,
+            precompressed: None
+        }
+    }
+
+    // Prefer a version of a file compressed in advance which the client
+    // accepts, unless the file was modified after it
+    pub fn with_precompressed(mut self, request: &Request, path: &Path, metadata: &Metadata) -> Self {
+        let available: Vec<_> = Encoding::PRECOMPRESSED.iter().filter_map(|&encoding| {
+            let precompressed = precompressed_path(path, encoding);
+            let precompressed_metadata = fs::metadata(&precompressed).ok()?;
+            let is_current = precompressed_metadata.modified().ok()? >= metadata.modified().ok()?;
+            if precompressed_metadata.is_file() && is_current {
+                Some((encoding, precompressed, precompressed_metadata))
+            } else {
+                None
+            }
+        }).collect();
+        if available.is_empty() {
+            return self;
+        }
+
+        self.varies = true;
+        let encoding = accepted(request.headers(), |encoding| {
+            available.iter().any(|&(available, _, _)| available == encoding)
+        });
+        if let Some(encoding) = encoding {
+            let (_, path, metadata) = available.into_iter()
+                .find(|&(available, _, _)| available == encoding).unwrap();
+            self.encoding = Some(encoding);
+            self.precompressed = Some((path, metadata));
+        }
+        self
+    }
+
+    // Send the body as it is, e.g. for parts of it, which still varies if
+    // it would be compressed otherwise
+    pub fn identity(&mut self) {
+        self.encoding = None;
+        self.precompressed = None;
+    }
+
+    // Add the Vary and Content-Encoding headers to a response
+    pub fn add_to<'a>(&self, response: Response<'a>) -> Response<'a> {
+        let response = if self.varies { response.header("Vary", "Accept-Encoding") } else { response };
+        match self.encoding {
+            Some(encoding) => response.header("Content-Encoding", encoding.token()),
+            None => response
+        }
+    }
+}
+
+
+fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    mime::is_text(mime) || COMPRESSIBLE.contains(&mime)
+}
+
+
+// Whether a file is a version of another one compressed in advance, like
+// "style.css.gz" next to "style.css"
+pub fn is_precompressed(path: &Path) -> bool {
+    Encoding::PRECOMPRESSED.iter().any(|encoding| {
+        path.extension().is_some_and(|extension| extension == encoding.extension())
+            && path.with_extension("").is_file()
+    })
+}
+
+fn precompressed_path(path: &Path, encoding: Encoding) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(encoding.extension());
+    path.with_file_name(file_name)
+}
+
+
+// The encoding with the highest quality value in Accept-Encoding, like
+// "gzip;q=0.8, br", of those which are available. None if the client wants
+// the body as it is.
+fn accepted(headers: &Headers, is_available: impl Fn(Encoding) -> bool) -> Option<Encoding> {
+    let mut best = None;
+    let mut best_quality = 0;
+    for encoding in Encoding::ALL.iter().copied().filter(|&encoding| is_available(encoding)) {
+        let quality = quality_of(headers, encoding);
+        if quality > best_quality {
+            best = Some(encoding);
+            best_quality = quality;
+        }
+    }
+    // Sending the body as it is is always acceptable, and preferred if the
+    // client says so
+    let identity = quality_of_token(headers, "identity").unwrap_or(1);
+    if identity > best_quality {
+        None
+    } else {
+        best
+    }
+}
+
+// The quality value of an encoding in thousandths, 0 if it isn't accepted
+fn quality_of(headers: &Headers, encoding: Encoding) -> u32 {
+    let by_name = match encoding {
+        Encoding::Gzip => quality_of_token(headers, "gzip").or_else(|| quality_of_token(headers, "x-gzip")),
+        _ => quality_of_token(headers, encoding.token())
+    };
+    by_name.or_else(|| quality_of_token(headers, "*")).unwrap_or(0)
+}
+
+// The quality value given for a token, if it is listed
+fn quality_of_token(headers: &Headers, token: &str) -> Option<u32> {
+    headers.get_list("accept-encoding").find_map(|item| {
+        let mut params = item.split(';');
+        if !params.next()?.trim().eq_ignore_ascii_case(token) {
+            return None;
+        }
+        let quality = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1000), |(_, value)| parse_quality(value.trim()));
+        // An invalid value makes the whole item count as not accepted
+        Some(quality.unwrap_or(0))
+    })
+}
+
+// A quality value like "0.5" or "1.000", in thousandths
+fn parse_quality(value: &str) -> Option<u32> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let thousandths = format!("{:0<3}", fraction).parse::<u32>().ok()?;
+    match whole {
+        "0" => Some(thousandths),
+        "1" if thousandths == 0 => Some(1000),
+        _ => None
+    }
+}
+
+
+// Compress a body in memory, like an auto index
+pub fn compress(data: &[u8], encoding: Encoding) -> Result<Vec<u8>> {
+    let mut compressed = Vec::with_capacity(data.len() / 2);
+    Compressed::new(data, encoding)?.read_to_end(&mut compressed)?;
+    Ok(compressed)
+}
+
+
+// A body which is compressed while it is read from another one
+pub struct Compressed<R> {
+    inner: R,
+    // None once the other body ended and this one is complete
+    encoder: Option<Encoder>,
+    // Whether what is read is sent right away, as the other body may be
+    // streamed in pieces, like the output of an executable which is still
+    // working on the rest. Otherwise, chunks of it are compressed at once.
+    is_stream: bool,
+    // Compressed data which wasn't read yet
+    output: Vec<u8>,
+    position: usize
+}
+
+impl<R: Read> Compressed<R> {
+    // Compress a body which is read in full, like a file
+    pub fn new(inner: R, encoding: Encoding) -> Result<Self> {
+        Ok(Self {
+            inner,
+            encoder: Some(Encoder::new(encoding)?),
+            is_stream: false,
+            output: Vec::new(),
+            position: 0
+        })
+    }
+
+    // Compress a body whose parts are sent as soon as they are read
+    pub fn stream(inner: R, encoding: Encoding) -> Result<Self> {
+        Ok(Self { is_stream: true, ..Self::new(inner, encoding)? })
+    }
+
+    // Read as much as the chunk holds, or less if the body is streamed
+    fn read_chunk(&mut self, chunk: &mut [u8]) -> Result<usize> {
+        let mut length = 0;
+        while length < chunk.len() {
+            match self.inner.read(&mut chunk[length..])? {
+                0 => break,
+                bytes_read => length += bytes_read
+            }
+            if self.is_stream {
+                break;
+            }
+        }
+        Ok(length)
+    }
+}
+
+impl<R: Read> Read for Compressed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        // Compressing a chunk may yield no data yet, which is kept until
+        // more is compressed
+        while self.position == self.output.len() {
+            if self.encoder.is_none() {
+                return Ok(0);
+            }
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let length = self.read_chunk(&mut chunk)?;
+            let mut encoder = self.encoder.take().unwrap();
+            if length == 0 {
+                self.output = encoder.finish()?;
+            } else {
+                encoder.write_all(&chunk[..length])?;
+                if self.is_stream {
+                    encoder.flush()?;
+                }
+                self.output = encoder.take_output();
+                self.encoder = Some(encoder);
+            }
+            self.position = 0;
+        }
+        let bytes_read = (&self.output[self.position..]).read(buf)?;
+        self.position += bytes_read;
+        Ok(bytes_read)
+    }
+}
+
+
+// The compressors, which write into a buffer that is emptied as it is read
+enum Encoder {
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::Encoder<'static, Vec<u8>>),
+    Gzip(GzEncoder<Vec<u8>>)
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Result<Self> {
+        Ok(match encoding {
+            Encoding::Brotli => Encoder::Brotli(Box::new(
+                CompressorWriter::new(Vec::new(), CHUNK_SIZE, BROTLI_QUALITY, BROTLI_WINDOW))),
+            Encoding::Zstd => Encoder::Zstd(zstd::Encoder::new(Vec::new(), ZSTD_LEVEL)?),
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::new(GZIP_LEVEL)))
+        })
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        mem::take(match self {
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+            Encoder::Gzip(encoder) => encoder.get_mut()
+        })
+    }
+
+    // End the compressed data, returning what is left of it
+    fn finish(self) -> Result<Vec<u8>> {
+        match self {
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            Encoder::Gzip(encoder) => encoder.finish()
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Encoder::Brotli(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Encoder::Brotli(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            Encoder::Gzip(encoder) => encoder.flush()
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// Conditional requests, which only get a response if the client's copy of a
+// page is outdated (If-None-Match, If-Modified-Since) or up to date
+// (If-Match, If-Unmodified-Since), and ranges of it only if it is current
+// (If-Range)
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::Metadata;
+use std::hash::Hasher;
+use std::os::unix::fs::MetadataExt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use micro_http_server::{Method, Request, Response, StatusCode};
+use httpdate::{fmt_http_date, parse_http_date};
+use crate::compression::Encoding;
+
+
+// What identifies the version of a page
+pub struct Validators {
+    // An entity tag, like "a1-6-b2" or W/"a1-6-b2"
+    etag: String,
+    last_modified: Option<SystemTime>
+}
+
+impl Validators {
+    // The validators of a file, from its inode, size and modification time.
+    // A weak tag is used for pages whose content depends on other files as
+    // well, like transcluded ones, which are only roughly the same for the
+    // same file.
+    pub fn of_file(metadata: &Metadata, is_weak: bool) -> Self {
+        let modified = metadata.modified().ok();
+        let nanos = modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_nanos());
+        let tag = format!("\"{:x}-{:x}-{:x}\"", metadata.ino(), metadata.size(), nanos);
+        Self {
+            etag: if is_weak { format!("W/{}", tag) } else { tag },
+            last_modified: modified
+        }
+    }
+
+    // The validators of a page generated by the server, like an auto index,
+    // from its content
+    pub fn of_content(content: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(content);
+        Self {
+            etag: format!("W/\"{:x}\"", hasher.finish()),
+            last_modified: None
+        }
+    }
+
+    // Compressed versions of a page have tags of their own, like
+    // "a1-6-b2-gzip", as they are different data
+    pub fn with_encoding(mut self, encoding: Option<Encoding>) -> Self {
+        if let Some(encoding) = encoding {
+            self.etag.insert_str(self.etag.len() - 1, &format!("-{}", encoding.token()));
+        }
+        self
+    }
+
+    // Add the validators to a response
+    pub fn add_to<'a>(&self, response: Response<'a>) -> Response<'a> {
+        let response = response.header("ETag", self.etag.clone());
+        match self.last_modified {
+            Some(modified) => response.header("Last-Modified", fmt_http_date(modified)),
+            None => response
+        }
+    }
+
+    // Check the preconditions of a request, in the order RFC 9110 gives.
+    // Returns the status to respond with instead of the page, if any.
+    pub fn check(&self, request: &Request) -> Option<StatusCode> {
+        let headers = request.headers();
+        let is_get = matches!(request.method(), Method::GET | Method::HEAD);
+
+        if headers.contains("if-match") {
+            if !headers.get_list("if-match").any(|tag| tag == "*" || strong_eq(tag, &self.etag)) {
+                return Some(StatusCode::PreconditionFailed);
+            }
+        } else if let Some(since) = headers.get("if-unmodified-since").and_then(|date| parse_http_date(date).ok()) {
+            if self.modified_after(since) {
+                return Some(StatusCode::PreconditionFailed);
+            }
+        }
+
+        let unchanged = if is_get { StatusCode::NotModified } else { StatusCode::PreconditionFailed };
+        if headers.contains("if-none-match") {
+            if headers.get_list("if-none-match").any(|tag| tag == "*" || weak_eq(tag, &self.etag)) {
+                return Some(unchanged);
+            }
+        } else if let Some(since) = headers.get("if-modified-since").and_then(|date| parse_http_date(date).ok()) {
+            if is_get && self.last_modified.is_some() && !self.modified_after(since) {
+                return Some(unchanged);
+            }
+        }
+        None
+    }
+
+    // Whether the value of an If-Range header names this version, in which
+    // case the requested ranges are sent rather than the whole page
+    pub fn matches_if_range(&self, value: &str) -> bool {
+        if value.starts_with('"') || value.starts_with("W/") {
+            strong_eq(value, &self.etag)
+        } else {
+            match (parse_http_date(value), self.last_modified) {
+                (Ok(date), Some(modified)) => date == truncate(modified),
+                _ => false
+            }
+        }
+    }
+
+    // Dates in headers only have seconds, so e.g. a file modified at 12.5s
+    // wasn't modified after 12s
+    fn modified_after(&self, date: SystemTime) -> bool {
+        self.last_modified.is_some_and(|modified| truncate(modified) > date)
+    }
+}
+
+
+fn truncate(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + Duration::from_secs(since.as_secs()),
+        Err(_) => time
+    }
+}
+
+
+// Strong comparison: both tags are strong and the same
+fn strong_eq(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && a == b
+}
+
+
+// Weak comparison: the tags are the same, whether they are weak or not
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
@@ -0,0 +1,223 @@
+// Configuration from the command line
+
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use micro_http_server::{Limits, TrustedProxies};
+use crate::forwarded;
+use anyhow::{Error, bail};
+
+
+pub struct Config {
+    // Where the server accepts connections
+    pub listen: Vec<Listen>,
+    // Permissions of Unix domain sockets
+    pub unix_mode: Option<u32>,
+    // Whether sockets on IPv6 addresses only accept IPv6 connections
+    pub ipv6_only: bool,
+    // Number of connections which are served at the same time
+    pub workers: usize,
+    // Number of connections which may wait for a worker before further
+    // connections are turned away
+    pub queue_size: usize,
+    // Timeouts and size limits for requests
+    pub limits: Limits,
+    // Proxies which start their connections with a PROXY protocol header
+    pub proxy_protocol: Option<TrustedProxies>,
+    // Proxies whose Forwarded and X-Forwarded-* headers are honoured
+    pub trusted_proxies: TrustedProxies,
+    // The path the server is reached at, without a trailing "/"
+    pub base_path: String,
+    // A file in the format of /etc/mime.types with types for extensions
+    pub mime_types: Option<PathBuf>,
+    // How long requests may take to finish when the server shuts down
+    pub shutdown_timeout: Option<Duration>,
+    // Whether connections wait for requests, and static files are sent, on
+    // a single thread, so only requests occupy workers
+    #[cfg(feature = "event-loop")]
+    pub event_loop: bool,
+    // Port and certificate for HTTPS, if it is enabled
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsOptions>
+}
+
+pub enum Listen {
+    // A TCP socket on the given address
+    Tcp(SocketAddr),
+    // A Unix domain socket at the given path
+    Unix(PathBuf),
+    // The sockets passed by systemd socket activation
+    Systemd
+}
+
+#[cfg(feature = "tls")]
+pub struct TlsOptions {
+    // Addresses of the TCP sockets for HTTPS
+    pub listen: Vec<SocketAddr>,
+    // PEM files with the certificate chain and the private key
+    pub cert: PathBuf,
+    pub key: PathBuf
+}
+
+impl Config {
+    pub fn from_args() -> Result<Self, Error> {
+        Self::parse(env::args().skip(1))
+    }
+
+    // The first argument which isn't an option is the port, options are
+    // given as "--name value". Instead of or in addition to the port,
+    // "--listen <address>", "--unix <path>" (both any number of times) or
+    // "--systemd" may be given.
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, Error> {
+        let mut has_port = false;
+        let mut listen = Vec::new();
+        let mut unix_mode = None;
+        let mut ipv6_only = false;
+        let mut workers = 128;
+        let mut queue_size = 128;
+        let mut limits = Limits::default();
+        let mut proxy_protocol: Option<TrustedProxies> = None;
+        let mut trusted_proxies = TrustedProxies::default();
+        let mut base_path = String::new();
+        let mut mime_types = None;
+        let mut shutdown_timeout = Some(Duration::from_secs(30));
+        #[cfg(feature = "event-loop")]
+        let mut event_loop = false;
+        #[cfg(feature = "tls")]
+        let (mut tls_listen, mut cert, mut key) = (Vec::new(), None, None);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--listen" => listen.push(Listen::Tcp(parse_value(&arg, args.next())?)),
+                "--unix" => listen.push(Listen::Unix(parse_value(&arg, args.next())?)),
+                "--unix-mode" => unix_mode = Some(parse_mode(&arg, args.next())?),
+                "--systemd" => listen.push(Listen::Systemd),
+                "--ipv6-only" => ipv6_only = true,
+                "--workers" => workers = parse_value(&arg, args.next())?,
+                "--queue-size" => queue_size = parse_value(&arg, args.next())?,
+                "--idle-timeout" => limits.idle_timeout = parse_timeout(&arg, args.next())?,
+                "--header-timeout" => limits.header_read_timeout = parse_timeout(&arg, args.next())?,
+                "--write-timeout" => limits.write_timeout = parse_timeout(&arg, args.next())?,
+                "--max-request-line" => limits.max_request_line = parse_value(&arg, args.next())?,
+                "--max-headers" => limits.max_headers = parse_value(&arg, args.next())?,
+                "--max-header-size" => limits.max_header_size = parse_value(&arg, args.next())?,
+                "--max-body-size" => limits.max_body_size = parse_value(&arg, args.next())?,
+                "--proxy-protocol" => {
+                    add_proxy(proxy_protocol.get_or_insert_with(TrustedProxies::default), &arg, args.next())?
+                },
+                "--trusted-proxy" => add_proxy(&mut trusted_proxies, &arg, args.next())?,
+                "--base-path" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    match forwarded::base_path(&value) {
+                        Some(path) => base_path = path,
+                        None => bail!("Invalid value for {}: {}", arg, value)
+                    }
+                },
+                "--mime-types" => mime_types = Some(parse_value(&arg, args.next())?),
+                "--shutdown-timeout" => shutdown_timeout = parse_timeout(&arg, args.next())?,
+                #[cfg(feature = "event-loop")]
+                "--event-loop" => event_loop = true,
+                #[cfg(not(feature = "event-loop"))]
+                "--event-loop" => bail!("{} requires building with the event-loop feature", arg),
+                #[cfg(feature = "tls")]
+                "--tls-port" => tls_listen.push(all_interfaces(parse_value(&arg, args.next())?)),
+                #[cfg(feature = "tls")]
+                "--tls-listen" => tls_listen.push(parse_value(&arg, args.next())?),
+                #[cfg(feature = "tls")]
+                "--cert" => cert = Some(parse_value(&arg, args.next())?),
+                #[cfg(feature = "tls")]
+                "--key" => key = Some(parse_value(&arg, args.next())?),
+                #[cfg(not(feature = "tls"))]
+                "--tls-port" | "--tls-listen" | "--cert" | "--key" => {
+                    bail!("{} requires building with the tls feature", arg)
+                },
+                _ if !has_port && !arg.starts_with("--") => {
+                    listen.push(Listen::Tcp(all_interfaces(arg.parse()?)));
+                    has_port = true;
+                },
+                _ => bail!("Unexpected argument: {}", arg)
+            }
+        }
+
+        if workers == 0 {
+            bail!("--workers must be at least 1");
+        }
+
+        if listen.is_empty() {
+            bail!("No port given");
+        }
+        if unix_mode.is_some() && !listen.iter().any(|l| matches!(l, Listen::Unix(_))) {
+            bail!("--unix-mode requires --unix");
+        }
+
+        #[cfg(feature = "tls")]
+        let tls = match (tls_listen.is_empty(), cert, key) {
+            (true, None, None) => None,
+            (false, Some(cert), Some(key)) => Some(TlsOptions { listen: tls_listen, cert, key }),
+            _ => bail!("--tls-port or --tls-listen, --cert and --key must be given together")
+        };
+
+        Ok(Self {
+            listen,
+            unix_mode,
+            ipv6_only,
+            workers,
+            queue_size,
+            limits,
+            proxy_protocol,
+            trusted_proxies,
+            base_path,
+            mime_types,
+            shutdown_timeout,
+            #[cfg(feature = "event-loop")]
+            event_loop,
+            #[cfg(feature = "tls")]
+            tls
+        })
+    }
+}
+
+
+// A port alone means listening on all IPv4 interfaces
+fn all_interfaces(port: u16) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))
+}
+
+fn parse_value<T>(name: &str, value: Option<String>) -> Result<T, Error>
+where T: FromStr, T::Err: std::error::Error + Send + Sync + 'static
+{
+    match value {
+        Some(value) => Ok(value.parse()?),
+        None => bail!("No value given for {}", name)
+    }
+}
+
+// Timeouts are given in seconds, where 0 means no timeout
+fn parse_timeout(name: &str, value: Option<String>) -> Result<Option<Duration>, Error> {
+    let seconds = parse_value(name, value)?;
+    if seconds == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(Duration::from_secs(seconds)))
+    }
+}
+
+// Proxies are given by their IP address, or as "unix" for all clients of Unix
+// domain sockets
+fn add_proxy(trusted: &mut TrustedProxies, name: &str, value: Option<String>) -> Result<(), Error> {
+    match parse_value::<String>(name, value)?.as_str() {
+        "unix" => trusted.unix = true,
+        addr => trusted.addrs.push(addr.parse::<IpAddr>()?)
+    }
+    Ok(())
+}
+
+// Permissions are given in octal, like for chmod
+fn parse_mode(name: &str, value: Option<String>) -> Result<u32, Error> {
+    match value {
+        Some(value) => Ok(u32::from_str_radix(&value, 8)?),
+        None => bail!("No value given for {}", name)
+    }
+}
@@ -1,10 +1,38 @@
-pub const ERROR_404: &'static str = format_html!("<title>Nothing</title>",
+pub const ERROR_400: &str = format_html!("<title>Bad Request</title>",
+    "<h1>The server could not understand your request.</h1>");
+
+pub const ERROR_404: &str = format_html!("<title>Nothing</title>",
     "<h1>The page you are looking for does not exist.</h1>");
 
-pub const ERROR_500: &'static str = format_html!("<title>Error</title>",
+pub const ERROR_405: &str = format_html!("<title>Not Allowed</title>",
+    "<h1>The page you requested does not support this kind of request.</h1>");
+
+pub const ERROR_408: &str = format_html!("<title>Request Timeout</title>",
+    "<h1>Your request took too long to arrive.</h1>");
+
+pub const ERROR_412: &str = format_html!("<title>Precondition Failed</title>",
+    "<h1>The page has changed since you last saw it.</h1>");
+
+pub const ERROR_413: &str = format_html!("<title>Payload Too Large</title>",
+    "<h1>The data you sent is larger than the server accepts.</h1>");
+
+pub const ERROR_414: &str = format_html!("<title>URI Too Long</title>",
+    "<h1>The address you requested is longer than the server accepts.</h1>");
+
+pub const ERROR_416: &str = format_html!("<title>Range Not Satisfiable</title>",
+    "<h1>The part of the file you requested does not exist.</h1>");
+
+pub const ERROR_431: &str = format_html!("<title>Headers Too Large</title>",
+    "<h1>Your request contains more headers than the server accepts.</h1>");
+
+pub const ERROR_500: &str = format_html!("<title>Error</title>",
     "<h1>The file you requested exists, but could not be served to you due to some error.</h1>");
 
-/*
-pub const ERROR_503: &'static str = format_html!("<title>Server Busy</title>",
+pub const ERROR_501: &str = format_html!("<title>Not Implemented</title>",
+    "<h1>The server does not know how to handle this kind of request.</h1>");
+
+pub const ERROR_503: &str = format_html!("<title>Server Busy</title>",
     "<h1>Server too busy to serve response. Sorry.</h1>");
-*/
+
+pub const ERROR_505: &str = format_html!("<title>Version Not Supported</title>",
+    "<h1>The server only understands HTTP/1.0 and HTTP/1.1.</h1>");
@@ -1,7 +1,7 @@
 // A file reader which supports transclusion
 
-use std::io::{Read, BufReader, Result};
-use std::fs::File;
+use std::io::{self, Read, BufReader, Result, ErrorKind};
+use std::fs::{File, Metadata};
 use std::path::{Path, PathBuf};
 use std::str::{self, FromStr};
 use std::ffi::OsStr;
@@ -15,7 +15,7 @@ const TRANSCLUDE_END_BYTE: u8 = b'}';
 
 const ESCAPE_BYTE: u8 = b'\\';
 
-const HTML_EXTENSION: &'static str = "html";
+const HTML_EXTENSION: &str = "html";
 
 
 // Count occurrences of the escape character and return how to handle the next
@@ -105,21 +105,34 @@ impl FileReader {
         Ok(())
     }
 
-    pub fn get_size(&self) -> usize {
-        // For files with transclusion enabled, we can't know the "true" size
-        // without traversing the full file, but we also have to return a size
-        // at the start of the HTTP response, so we use the same hack as with
-        // index_executable & form_executable and respond with maximum size
-        self.readers.get(0)
-            .and_then(|r| if !r.is_transclude_enabled {
-                    r.reader.get_ref()
-                        .metadata()
-                        .map(|m| m.len() as usize)
-                        .ok()
-                } else {
-                    Some(usize::MAX)
-                })
-            .unwrap_or(usize::MAX)
+    // Return the size of the file in bytes, or None if it is not known in
+    // advance. For files with transclusion enabled, we can't know the "true"
+    // size without traversing the full file.
+    pub fn get_size(&self) -> Option<usize> {
+        self.readers.first()
+            .filter(|r| !r.is_transclude_enabled)
+            .and_then(|r| r.reader.get_ref()
+                .metadata()
+                .map(|m| m.len() as usize)
+                .ok())
+    }
+
+    // Return the metadata of the file which was opened, which stays the same
+    // file even if the path is replaced meanwhile
+    pub fn metadata(&self) -> Result<Metadata> {
+        self.readers.first()
+            .ok_or(io::Error::from(ErrorKind::Other))
+            .and_then(|r| r.reader.get_ref().metadata())
+    }
+
+    // Return the file which was opened, e.g. to read parts of it directly.
+    // This should only be used before anything was read and for files without
+    // transclusion, whose contents are the file's.
+    pub fn into_file(mut self) -> Result<File> {
+        if self.readers.is_empty() {
+            return Err(io::Error::from(ErrorKind::Other));
+        }
+        Ok(self.readers.swap_remove(0).reader.into_inner())
     }
 }
 
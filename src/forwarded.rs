@@ -0,0 +1,159 @@
+// Where requests come from and which URLs they were made to, as told by
+// trusted reverse proxies in the Forwarded header (RFC 7239) or the
+// X-Forwarded-For/Proto/Host/Prefix headers, so redirects and links point to
+// the server as the client reaches it.
+
+use std::sync::OnceLock;
+use micro_http_server::{ClientAddr, Headers, Request, TrustedProxies};
+use urlencoding::encode;
+
+
+// Set once when the server starts
+static FORWARDING: OnceLock<Forwarding> = OnceLock::new();
+
+
+#[derive(Default)]
+pub struct Forwarding {
+    // Proxies whose headers are honoured
+    pub trusted: TrustedProxies,
+    // The path the server is reached at, e.g. "/users", unless a proxy tells
+    // otherwise. Empty if it is reached at "/".
+    pub base_path: String
+}
+
+impl Forwarding {
+    pub fn set(self) {
+        let _ = FORWARDING.set(self);
+    }
+
+    pub fn get() -> &'static Forwarding {
+        FORWARDING.get_or_init(Forwarding::default)
+    }
+}
+
+
+// Where a request came from and the URL it was made to
+pub struct Origin {
+    // The address of the client, e.g. to report errors with
+    pub client: String,
+    // The scheme and host the client made the request to, if a proxy told
+    scheme_host: Option<(String, String)>,
+    // Like Forwarding::base_path
+    base_path: String
+}
+
+impl Origin {
+    // Take the client's address from the connection, unless the proxy it
+    // came from is trusted and tells better
+    pub fn new(request: &Request, addr: &ClientAddr, peer_addr: &ClientAddr, forwarding: &Forwarding) -> Self {
+        let mut origin = Self {
+            client: addr.to_string(),
+            scheme_host: None,
+            base_path: forwarding.base_path.clone()
+        };
+        if !forwarding.trusted.contains(peer_addr) {
+            return origin;
+        }
+
+        let headers = request.headers();
+        let (client, proto, host) = match headers.get_list("forwarded").last() {
+            Some(element) => forwarded_pairs(element),
+            // Only the last value is added by the proxy we trust, the others
+            // come from whoever connected to it
+            None => (
+                last_value(headers, "x-forwarded-for"),
+                last_value(headers, "x-forwarded-proto"),
+                last_value(headers, "x-forwarded-host")
+            )
+        };
+        if let Some(client) = client {
+            origin.client = client;
+        }
+        let proto = proto.map(|proto| proto.to_ascii_lowercase())
+            .filter(|proto| proto == "http" || proto == "https");
+        let host = host.filter(|host| is_host(host));
+        if let (Some(proto), Some(host)) = (proto, host) {
+            origin.scheme_host = Some((proto, host));
+        }
+        if let Some(prefix) = last_value(headers, "x-forwarded-prefix") {
+            if let Some(prefix) = base_path(&prefix) {
+                origin.base_path = prefix;
+            }
+        }
+        origin
+    }
+
+    // The path of a resource on this server as the client reaches it. The
+    // path must be percent-encoded. Leading slashes are collapsed, since a
+    // path like "//example.com/" would be taken for another host.
+    pub fn path(&self, path: &str) -> String {
+        format!("{}/{}", self.base_path, path.trim_start_matches('/'))
+    }
+
+    // The URL of a resource on this server, e.g. for a Location header. It
+    // is only absolute if a proxy told which scheme and host were requested.
+    pub fn url(&self, path: &str) -> String {
+        match &self.scheme_host {
+            Some((scheme, host)) => format!("{}://{}{}", scheme, host, self.path(path)),
+            None => self.path(path)
+        }
+    }
+}
+
+
+// Percent-encode each segment of a decoded path, so it can be used in URLs
+// and HTML attributes
+pub fn encode_path(path: &str) -> String {
+    path.split('/').map(|segment| encode(segment)).collect::<Vec<_>>().join("/")
+}
+
+
+// Check and normalize a base path, which has to start with "/" and is used
+// without a trailing "/". Returns None if it is invalid.
+pub fn base_path(path: &str) -> Option<String> {
+    let path = path.trim_end_matches('/');
+    let is_valid = path.is_empty() || (path.starts_with('/') && !path.starts_with("//") && path.bytes().all(|b| {
+        b.is_ascii_alphanumeric() || b"-._~!$()*+,;=:@/%".contains(&b)
+    }));
+    if is_valid {
+        Some(path.to_owned())
+    } else {
+        None
+    }
+}
+
+
+// The last value of a header which may be a list, like "X-Forwarded-For"
+fn last_value(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_list(name).last().map(str::to_owned)
+}
+
+
+// The "for", "proto" and "host" parameters of an element of the Forwarded
+// header, e.g. for="[2001:db8::1]:4711";proto=https;host=example.com
+fn forwarded_pairs(element: &str) -> (Option<String>, Option<String>, Option<String>) {
+    let (mut client, mut proto, mut host) = (None, None, None);
+    for pair in element.split(';') {
+        let (name, value) = match pair.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => continue
+        };
+        let value = value.strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .map(|value| value.replace('\\', ""))
+            .unwrap_or_else(|| value.to_owned());
+        match name.to_ascii_lowercase().as_str() {
+            "for" => client = Some(value),
+            "proto" => proto = Some(value),
+            "host" => host = Some(value),
+            _ => {}
+        }
+    }
+    (client, proto, host)
+}
+
+
+// Whether a host (with an optional port) can be used in a URL as it is
+fn is_host(host: &str) -> bool {
+    !host.is_empty() && host.bytes().all(|b| b.is_ascii_alphanumeric() || b"-.:[]_".contains(&b))
+}
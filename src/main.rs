@@ -3,107 +3,560 @@ mod html_common;
 mod error_pages;
 mod auto_index;
 mod file_reader;
+mod config;
+mod worker_pool;
+mod children;
+mod restart;
+mod forwarded;
+mod mime;
+mod ranges;
+mod conditional;
+mod compression;
 
 use file_reader::FileReader;
+use config::{Config, Listen};
+use worker_pool::WorkerPool;
+use children::RunningChild;
+use restart::Inherited;
+use forwarded::{Forwarding, Origin};
+use mime::MimeTypes;
+use ranges::{ByteRanges, Ranges};
+use conditional::Validators;
+use compression::{Compressed, Negotiated};
 
 use std::env;
-use micro_http_server::{MicroHTTP, Client, Request, FormData};
-use anyhow::Error;
-use std::thread;
+use micro_http_server::{
+    MicroHTTP, Listener, Client, Request, Method, Headers, RequestError, FormData, Multipart, QueryData, Response,
+    StatusCode
+};
+#[cfg(feature = "event-loop")]
+use micro_http_server::EventLoop;
+use anyhow::{Context, Error, bail};
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR2};
+use signal_hook::iterator::Signals;
+use std::mem;
+use std::thread::{self, JoinHandle};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 use std::path::{Path, PathBuf, Component};
-use std::fs::{OpenOptions, File, metadata};
-use std::io::{self, ErrorKind, Result, Read, Write, BufRead, BufReader};
+use std::fs::{self, OpenOptions, File, Permissions};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::io::{self, ErrorKind, Result, Read, BufRead, BufReader, Seek, SeekFrom};
 use std::result::Result as StdResult;
-use std::process::{Command, Stdio};
+use std::process::{self, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::collections::{HashMap, HashSet};
-use httpdate::fmt_http_date;
-use urlencoding;
+
+
+// How long clients are asked to wait before retrying when the server is busy
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+// How many clients may be turned away at the same time
+const MAX_REJECTING: usize = 64;
+
+// How long to wait before accepting connections again after accepting failed
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+// How often to check whether a thread ended while shutting down
+const JOIN_INTERVAL: Duration = Duration::from_millis(10);
+
+// Set once the server stops accepting connections
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
 
 
 fn main() -> StdResult<(), Error> {
-    let port = env::args().nth(1).unwrap().parse()?;
-    let server = MicroHTTP::new(("0.0.0.0", port))?;
+    let config = Config::from_args()?;
+    Forwarding {
+        trusted: config.trusted_proxies.clone(),
+        base_path: config.base_path.clone()
+    }.set();
+    let mut mime_types = MimeTypes::default();
+    if let Some(path) = &config.mime_types {
+        mime_types.load(path).with_context(|| format!("Could not read {}", path.display()))?;
+    }
+    mime_types.set();
+    let mut inherited = Inherited::from_env()?;
+    let mut http_server = match inherited.as_mut() {
+        Some(inherited) => server_on(mem::take(&mut inherited.http))?,
+        None => plain_server(&config)?
+    };
+    http_server.set_limits(config.limits);
+    http_server.set_proxy_protocol(config.proxy_protocol.clone());
+    #[cfg(feature = "tls")]
+    let https_server = match &config.tls {
+        Some(options) => {
+            let inherited = inherited.as_mut().map(|inherited| mem::take(&mut inherited.https));
+            let mut server = tls_server(options, config.ipv6_only, inherited)?;
+            server.set_limits(config.limits);
+            server.set_proxy_protocol(config.proxy_protocol.clone());
+            Some(Arc::new(server))
+        },
+        None => None
+    };
+    #[cfg(not(feature = "tls"))]
+    let https_server: Option<Arc<MicroHTTP>> = None;
+    let http_server = Arc::new(http_server);
+    let servers: Vec<_> = Some(http_server.clone()).into_iter().chain(https_server.clone()).collect();
+
+    let pool = Arc::new(WorkerPool::new(config.workers, config.queue_size, handle_client));
+    let mut signals = Signals::new([SIGTERM, SIGINT, SIGUSR2])?;
+    if let Some(inherited) = inherited {
+        inherited.ready()?;
+    }
+
+    #[cfg(feature = "event-loop")]
+    let accepting = if config.event_loop {
+        vec![run_event_loop(&servers, &pool)?]
+    } else {
+        accept_on_threads(&servers, &pool)
+    };
+    #[cfg(not(feature = "event-loop"))]
+    let accepting = accept_on_threads(&servers, &pool);
+
+    wait_for_shutdown(&mut signals, &http_server, https_server.as_deref());
+
+    // Stop accepting connections, and close the ones which are kept alive
+    // after their current request
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    for server in &servers {
+        server.stop_handle().stop();
+    }
+
+    // Asking again ends the server right away
+    thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            children::terminate_all();
+            process::exit(1);
+        }
+    });
+
+    // An event loop only ends once the requests were handled and it sent the
+    // remaining responses, while threads only accepting connections end
+    // right away
+    let deadline = config.shutdown_timeout.map(|timeout| Instant::now() + timeout);
+    let has_ended = join_until(accepting, deadline);
+    // Closing the sockets refuses new connections instead of leaving them
+    // waiting until the process exits
+    drop((servers, http_server, https_server));
 
+    if !(has_ended && pool.finish(deadline)) {
+        eprintln!("Terminating the requests which are still being handled");
+        children::terminate_all();
+    }
+    Ok(())
+}
+
+
+// Accept connections on a thread for each server
+fn accept_on_threads(servers: &[Arc<MicroHTTP>], pool: &Arc<WorkerPool<Client>>) -> Vec<JoinHandle<()>> {
+    servers.iter()
+        .map(|server| {
+            let server = server.clone();
+            let pool = pool.clone();
+            thread::spawn(move || accept_clients(&server, &pool))
+        })
+        .collect()
+}
+
+
+// Accept connections for all servers on a single thread, which also waits
+// for further requests on connections which are kept alive and sends static
+// files, so only requests which are being handled occupy a worker
+#[cfg(feature = "event-loop")]
+fn run_event_loop(servers: &[Arc<MicroHTTP>], pool: &Arc<WorkerPool<Client>>) -> StdResult<JoinHandle<()>, Error> {
+    let event_loop = EventLoop::new()?;
+    let servers = servers.to_vec();
+    let pool = pool.clone();
+    Ok(thread::spawn(move || {
+        let servers: Vec<&MicroHTTP> = servers.iter().map(|server| &**server).collect();
+        let result = event_loop.run(&servers, |client| {
+            if let Err(client) = pool.execute(client) {
+                reject_client(client);
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("Event loop failed: {}", e);
+            process::exit(1);
+        }
+    }))
+}
+
+
+// Wait for the threads to end, but not past `deadline` if there is one.
+// Returns whether they ended.
+fn join_until(threads: Vec<JoinHandle<()>>, deadline: Option<Instant>) -> bool {
+    for thread in threads {
+        while !thread.is_finished() {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return false;
+            }
+            thread::sleep(JOIN_INTERVAL);
+        }
+        drop(thread.join());
+    }
+    true
+}
+
+
+// Wait until the server is asked to shut down, or to restart, in which case
+// it shuts down once the new process accepts connections
+fn wait_for_shutdown(signals: &mut Signals, http_server: &MicroHTTP, https_server: Option<&MicroHTTP>) {
+    for signal in signals.forever() {
+        if signal != SIGUSR2 {
+            return;
+        }
+        match restart::restart(http_server, https_server) {
+            Ok(()) => {
+                eprintln!("Handed the sockets over to a new process");
+                return;
+            },
+            Err(e) => eprintln!("Failed to restart: {}", e)
+        }
+    }
+}
+
+
+// Create the server for plain HTTP connections, which accepts them on all
+// configured sockets
+fn plain_server(config: &Config) -> StdResult<MicroHTTP, Error> {
+    let mut listeners = Vec::new();
+    for listen in &config.listen {
+        match listen {
+            Listen::Tcp(addr) => listeners.push(bind_tcp(*addr, config.ipv6_only)?),
+            Listen::Unix(path) => listeners.push(bind_unix(path, config.unix_mode)?),
+            Listen::Systemd => {
+                let passed = Listener::from_systemd()?;
+                if passed.is_empty() {
+                    bail!("No sockets were passed by systemd");
+                }
+                listeners.extend(passed);
+            }
+        }
+    }
+    server_on(listeners)
+}
+
+
+fn bind_tcp(addr: SocketAddr, ipv6_only: bool) -> StdResult<Listener, Error> {
+    Listener::bind_tcp(addr, ipv6_only).with_context(|| format!("Could not listen on {}", addr))
+}
+
+
+// Bind a Unix domain socket and give it `mode`, if set. Until then, only the
+// server's user may connect, so no one else gets in before the mode applies.
+fn bind_unix(path: &Path, mode: Option<u32>) -> StdResult<Listener, Error> {
+    let bind = || Listener::bind_unix(path).with_context(|| format!("Could not listen on {}", path.display()));
+    let mode = match mode {
+        Some(mode) => mode,
+        None => return bind()
+    };
+    // Nothing else runs yet while the server starts, so changing the umask
+    // of the whole process doesn't affect other files
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = bind();
+    unsafe { libc::umask(umask) };
+    let listener = listener?;
+    fs::set_permissions(path, Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+
+// Create a server which accepts connections on all of the given listeners
+fn server_on(listeners: Vec<Listener>) -> StdResult<MicroHTTP, Error> {
+    let mut listeners = listeners.into_iter();
+    let mut server = MicroHTTP::from_listener(listeners.next().expect("No listeners"))?;
+    for listener in listeners {
+        server.add_listener(listener)?;
+    }
+    Ok(server)
+}
+
+
+// Create the server for HTTPS connections. The certificate is loaded again
+// whenever the process receives SIGHUP, e.g. after it was renewed.
+#[cfg(feature = "tls")]
+fn tls_server(options: &config::TlsOptions, ipv6_only: bool, inherited: Option<Vec<Listener>>) -> StdResult<MicroHTTP, Error> {
+    use micro_http_server::TlsConfig;
+    use signal_hook::consts::SIGHUP;
+
+    let tls = TlsConfig::from_pem_files(&options.cert, &options.key)?;
+    let mut signals = Signals::new([SIGHUP])?;
+    let reloaded = tls.clone();
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            match reloaded.reload() {
+                Ok(()) => eprintln!("Reloaded TLS certificate"),
+                Err(e) => eprintln!("Failed to reload TLS certificate: {}", e)
+            }
+        }
+    });
+
+    let listeners = match inherited {
+        Some(listeners) if !listeners.is_empty() => listeners,
+        _ => options.listen.iter()
+            .map(|addr| bind_tcp(*addr, ipv6_only))
+            .collect::<StdResult<_, _>>()?
+    };
+    let mut server = server_on(listeners)?;
+    server.set_tls(Some(tls));
+    Ok(server)
+}
+
+
+// Hand connections to the worker pool as they are accepted, until the server
+// is stopped
+fn accept_clients(server: &MicroHTTP, pool: &WorkerPool<Client>) {
     loop {
-        let client = server.next_client()?;
-        if let Some(client) = client {
-            thread::spawn(move || handle_client(client));
+        match server.next_client() {
+            Ok(Some(client)) => {
+                if let Err(client) = pool.execute(client) {
+                    reject_client(client);
+                }
+            },
+            Ok(None) => break,
+            Err(e) => {
+                // Errors here are usually caused by a single client or are
+                // temporary (e.g. running out of file descriptors), so keep
+                // accepting, but don't spin if the error persists.
+                eprintln!("Failed to accept connection: {}", e);
+                thread::sleep(ACCEPT_ERROR_DELAY);
+            }
         }
     }
 }
 
 
-fn handle_client(mut client: Client) -> Option<()> {
-    let (path_string, request) = client.request_mut().take()?;
+// Turn away a client because all workers are busy. This happens on a thread
+// of its own, since finishing a TLS handshake and waiting for the client to
+// read the response would hold up accepting other clients. Once too many
+// clients are being turned away, further ones are disconnected right away.
+fn reject_client(client: Client) {
+    static REJECTING: AtomicUsize = AtomicUsize::new(0);
 
-    let path = if path_string.starts_with("/") {
-        PathBuf::from(&path_string[1..])
-    } else {
-        PathBuf::from(&path_string)
+    if REJECTING.fetch_add(1, Ordering::SeqCst) >= MAX_REJECTING {
+        REJECTING.fetch_sub(1, Ordering::SeqCst);
+        return;
+    }
+    let spawned = thread::Builder::new().spawn(move || {
+        let addr = client.addr().clone();
+        let response = error_response(StatusCode::ServiceUnavailable, error_pages::ERROR_503)
+            .header("Retry-After", RETRY_AFTER.as_secs().to_string());
+        if let Err(e) = client.reject(response) {
+            eprintln!("{}: {}", addr, e);
+        }
+        REJECTING.fetch_sub(1, Ordering::SeqCst);
+    });
+    if spawned.is_err() {
+        REJECTING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+
+// Serve requests from the client until it closes the connection, stops
+// sending requests or a response requires closing the connection.
+fn handle_client(mut client: Client) {
+    loop {
+        match client.next_request() {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(e) => {
+                // Idle clients timing out is expected, don't report it
+                if !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+                    eprintln!("{}: {}", client.addr(), e);
+                }
+                break;
+            }
+        }
+
+        if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            client.close_after_response();
+        }
+        handle_request(&mut client);
+
+        // An event loop waits for the next request by itself
+        if !client.keep_alive() || client.has_event_loop() {
+            break;
+        }
+    }
+}
+
+
+fn handle_request(client: &mut Client) {
+    let request = match client.request_mut().take() {
+        Some(request) => request,
+        None => {
+            if let Err(e) = handle_invalid_request(client) {
+                eprintln!("{}", e);
+            }
+            return;
+        }
     };
 
+    let origin = Origin::new(&request, client.addr(), client.peer_addr(), Forwarding::get());
+    if let Err(e) = handle_valid_request(&request, &origin, client) {
+        respond_to_handler_error(e, &origin, client);
+    }
+}
+
+
+// Report an error in handling a request. The client still waits for a
+// response, which can't be finished if it was started, so the connection is
+// closed in that case.
+fn respond_to_handler_error(e: io::Error, origin: &Origin, client: &mut Client) {
+    eprintln!("{}: {}", origin.client, e);
+    let sent = !client.has_responded() && client.send(
+        error_response(StatusCode::InternalServerError, error_pages::ERROR_500)).is_ok();
+    if !sent {
+        client.close_after_response();
+    }
+}
+
+
+// Map the path of a request to a location in the file system
+fn resolve_path(path_string: &str) -> PathBuf {
+    let path = PathBuf::from(path_string.strip_prefix('/').unwrap_or(path_string));
+
     // Prevent accessing directories that are not descendants of /home by disabling
     // using parent directories (../) in paths.
-    let mut components = path.components().filter(|c| {
-        match c {
-            Component::ParentDir => false,
-            _ => true
-        }
-    });
+    let mut components = path.components().filter(|c| !matches!(c, Component::ParentDir));
     // Assuming first component is user name
-    let user = components.nth(0).and_then(|c| match c {
+    let user = components.next().and_then(|c| match c {
         Component::Normal(c) => Some(c),
         _ => None
     });
-    let file_path = match user {
+    match user {
         Some(user) => {
             let path = components.fold(PathBuf::new(), |mut p, c| { p.push(c); p });
             Path::new("/home").join(user).join("www").join(path)
         },
         None => PathBuf::from("/home")
+    }
+}
+
+
+// Return the value of the "Allow" header for the given location, i.e. the
+// methods for which a request would not result in a 404 or 405 response.
+fn allowed_methods(file_path: &Path) -> Option<&'static str> {
+    if file_path.ends_with("form_executable") && file_path.is_file() {
+        Some("POST, OPTIONS")
+    } else if !file_path.exists() || file_path.ends_with("allowed_variables") {
+        None
+    } else if file_path.join("form_executable").is_file() {
+        Some("GET, HEAD, POST, OPTIONS")
+    } else {
+        Some("GET, HEAD, OPTIONS")
+    }
+}
+
+
+// Respond to a request which could not be parsed or used a method we don't support
+fn handle_invalid_request(client: &mut Client) -> Result<()> {
+    match client.request_error().cloned() {
+        Some(e) => respond_to_request_error(&e, client),
+        None => Ok(())
+    }
+}
+
+
+// Build a response showing one of the pages in error_pages
+fn error_response(status: StatusCode, page: &'static str) -> Response<'static> {
+    Response::new(status)
+        .header("Content-Type", mime::HTML)
+        .body(page.as_bytes())
+}
+
+
+// Respond to an error in the body of a request if it was caused by the client,
+// otherwise pass it on.
+fn respond_to_body_error(e: io::Error, client: &mut Client) -> Result<()> {
+    match RequestError::from_io_error(&e) {
+        Some(e) => respond_to_request_error(&e.clone(), client),
+        None => Err(e)
+    }
+}
+
+
+fn respond_to_request_error(e: &RequestError, client: &mut Client) -> Result<()> {
+    eprintln!("{}: {}", client.addr(), e);
+
+    // A path which doesn't exist has no methods to allow, so it isn't there
+    // for any method
+    let allow = match e {
+        RequestError::MethodNotAllowed(_, path_string) => allowed_methods(&resolve_path(path_string)),
+        _ => None
+    };
+    let status = match e {
+        RequestError::MethodNotAllowed(_, _) if allow.is_none() => StatusCode::NotFound,
+        _ => e.status()
+    };
+    let page = match status {
+        StatusCode::BadRequest => error_pages::ERROR_400,
+        StatusCode::NotFound => error_pages::ERROR_404,
+        StatusCode::MethodNotAllowed => error_pages::ERROR_405,
+        StatusCode::RequestTimeout => error_pages::ERROR_408,
+        StatusCode::PayloadTooLarge => error_pages::ERROR_413,
+        StatusCode::URITooLong => error_pages::ERROR_414,
+        StatusCode::RequestHeaderFieldsTooLarge => error_pages::ERROR_431,
+        StatusCode::NotImplemented => error_pages::ERROR_501,
+        StatusCode::HTTPVersionNotSupported => error_pages::ERROR_505,
+        _ => error_pages::ERROR_500
     };
 
-    let response_status = if file_path.is_dir() && !path_string.ends_with("/") {
-        let query = match request {
-            Request::GET(query, _) => Some(query),
-            Request::POST(_, Some(FormData::KeyVal(query))) => Some(query),
-            _ => None
-        };
-        let query_string = match query {
-            Some(query) => {
-                let mut query_string = String::with_capacity(1024);
-                if !query.is_empty() {
-                    query_string.push('?');
-                    for (i, (k, v)) in query.iter().enumerate() {
-                        if i > 0 {
-                            query_string.push('&');
-                        }
-                        query_string.push_str(k);
-                        if !v.is_empty() {
-                            query_string.push('=');
-                            query_string.push_str(v);
-                        }
-                    }
-                }
-                query_string
-            }
-            None => String::new()
+    let mut response = error_response(status, page);
+    if let Some(allow) = allow {
+        response = response.header("Allow", allow);
+    }
+    client.send(response)?;
+    Ok(())
+}
+
+
+fn handle_valid_request(request: &Request, origin: &Origin, client: &mut Client) -> Result<()> {
+    let path_string = request.path();
+    let file_path = resolve_path(path_string);
+
+    let is_options = request.method() == Method::OPTIONS;
+    if file_path.is_dir() && !path_string.ends_with("/") && !is_options {
+        // Pass on the query exactly as the client sent it
+        let query_string = match request.method() {
+            Method::POST => match client.form_data(request.headers()) {
+                Ok(Some(FormData::KeyVal(query))) if !query.is_empty() => format!("?{}", query),
+                Ok(_) => String::new(),
+                Err(e) => return respond_to_body_error(e, client)
+            },
+            _ => request.query_string().map(|q| format!("?{}", q)).unwrap_or_default()
         };
 
-        let location = format!("Location: {}/{}", path_string, query_string);
-        client.respond("302 Found", &[], &vec![location]).map(|_| ())
+        let location = origin.url(&format!("{}/{}", request.raw_path(), query_string));
+        client.send(Response::new(StatusCode::Found).header("Location", location)).map(|_| ())
     } else {
-        match request {
-            Request::GET(query, headers) => handle_get(&file_path, query, headers, client),
-            Request::POST(_, mut data) => handle_post(&file_path, &mut data, client)
+        match request.method() {
+            Method::GET | Method::HEAD => handle_get(&file_path, request, origin, client),
+            Method::POST => handle_post(&file_path, request.headers(), client),
+            Method::OPTIONS => handle_options(path_string, &file_path, client)
         }
+    }
+}
+
+
+// Helper function to respond to OPTIONS requests
+fn handle_options(path_string: &str, file_path: &Path, client: &mut Client) -> Result<()> {
+    let allow = if path_string == "*" {
+        Some("GET, HEAD, POST, OPTIONS")
+    } else {
+        allowed_methods(file_path)
     };
 
-    if let Err(e) = response_status {
-        eprintln!("{}", e);
+    match allow {
+        Some(allow) => {
+            client.send(Response::ok().header("Allow", allow))?;
+        },
+        None => {
+            client.send(error_response(StatusCode::NotFound, error_pages::ERROR_404))?;
+        }
     }
-
-    Some(())
+    Ok(())
 }
 
 
@@ -121,11 +574,12 @@ where P: AsRef<Path>
 }
 
 
-// Helper function to respond to GET requests
-fn handle_get(
-    file_path: &PathBuf, mut query: HashMap<String, String>,
-    headers: HashMap<String, String>, mut client: Client) -> Result<()>
-{
+// Helper function to respond to GET and HEAD requests. The client leaves out
+// the response body for HEAD requests by itself, but executables are not run.
+fn handle_get(file_path: &Path, request: &Request, origin: &Origin, client: &mut Client) -> Result<()> {
+    let query = request.query();
+    let headers = request.headers();
+    let is_head = request.method() == Method::HEAD;
     let mut file_path = file_path.to_owned();
 
     if file_path.is_dir() {
@@ -154,87 +608,209 @@ fn handle_get(
                 .and_then(|s| s.parse().ok()).unwrap_or(1) - 1;
 
             // serve autoindex
-            let index = if &file_path == &Path::new("/home") {
-                auto_index::generate_index(&file_path, Some("People"), |entry| {
+            let url_path = origin.path(&forwarded::encode_path(request.path()));
+            let index = if file_path == Path::new("/home") {
+                auto_index::generate_index(&file_path, &url_path, Some("People"), |entry| {
                     let entry = entry.ok()?;
                     if entry.file_type().ok()?.is_dir() && entry.path().join("www").exists() {
-                        return Some(entry);
+                        Some(entry)
                     } else {
                         None
                     }
                 }, page_size, page_number)
             } else {
                 auto_index::generate_index(
-                    &file_path, None, |entry| { entry.ok() },
+                    &file_path, &url_path, None, |entry| { entry.ok() },
                     page_size, page_number)
             };
 
             match index {
                 Ok(index) => {
-                    client.respond(
-                        "200 OK",
-                        &index.as_bytes(),
-                        &vec!["Cache-Control: max-age=30".to_owned()])?;
+                    let negotiated = Negotiated::new(request, Some(mime::HTML), Some(index.len()));
+                    let validators = Validators::of_content(index.as_bytes()).with_encoding(negotiated.encoding);
+                    if respond_to_conditions(&validators, &negotiated, request, client)? {
+                        return Ok(());
+                    }
+                    let body = match negotiated.encoding {
+                        Some(encoding) => compression::compress(index.as_bytes(), encoding)?,
+                        None => index.into_bytes()
+                    };
+                    let response = validators.add_to(Response::ok().header("Content-Type", mime::HTML));
+                    client.send(negotiated.add_to(response)
+                        .header("Cache-Control", "max-age=30")
+                        .body(body))?;
                 },
                 Err(_) => {
-                    client.respond(
-                        "500 Internal Server Error",
-                        error_pages::ERROR_500.as_bytes(), &vec![])?;
+                    client.send(error_response(StatusCode::InternalServerError, error_pages::ERROR_500))?;
                 }
             }
         } else if file_path.ends_with("index_executable") {
+            // The output may be anything, but is most likely HTML
+            let negotiated = Negotiated::new(request, None, None);
+            let response = negotiated.add_to(Response::ok()).header("Cache-Control", "no-cache");
+            // HEAD fails the same way as GET without the allowed variables
             let allowed_variables_file = get_adjacent_allowed_variables_file(&file_path)?;
             let allowed_variables = get_allowed_variables(allowed_variables_file)?;
-            filter_env_variables(&mut query, &allowed_variables);
+            if is_head {
+                client.send(response.body_stream(io::empty()))?;
+                return Ok(());
+            }
+
+            let mut vars = first_values(query);
+            filter_env_variables(&mut vars, &allowed_variables);
             // run program
-            let child_process = Command::new(file_path.as_os_str())
-                .envs(query)
+            let mut child_process = RunningChild::spawn(Command::new(file_path.as_os_str())
+                .envs(vars)
+                .env("QUERY_STRING", request.query_string().unwrap_or(""))
                 .arg(file_path)
-                .stdout(Stdio::piped())
-                .spawn()?;
-            // This is a really nasty hack, but to get around the requirement of
-            // the content length header, just set it to the max possible value.
-            // modern browsers will be able to handle this even if it's not standard.
-            client.respond_chunked(
-                "200 OK",
-                child_process.stdout.expect("Capturing stdout"),
-                usize::MAX,
-                &vec!["Cache-Control: no-cache".to_owned()])?;
-        } else {
-            // serve file
-            let modified = metadata(&file_path).and_then(|m| m.modified())?;
-            let modified_string = fmt_http_date(modified);
-            if let Some(modified_since) = headers.get("if-modified-since") {
-                if modified_since == &modified_string {
-                    client.respond("304 Not Modified", b"", &vec![])?;
-                    return Ok(());
+                .stdout(Stdio::piped()))?;
+            let stdout = child_process.stdout.take().expect("Capturing stdout");
+            match negotiated.encoding {
+                Some(encoding) => {
+                    client.send(response.body_stream(Compressed::stream(stdout, encoding)?))?;
+                },
+                None => {
+                    client.send(response.body_stream(stdout))?;
                 }
             }
-
-            let headers = vec![
-                format!("Last-Modified: {}", modified_string),
-                "Cache-Control: max-age=30".to_owned()
-            ];
-
+        } else {
+            // serve file
             match FileReader::new(&file_path) {
                 Ok(r) => {
-                    let size = r.get_size();
-                    client.respond_chunked("200 OK", r, size, &headers)?;
+                    let metadata = r.metadata()?;
+                    let content_type = MimeTypes::get().of_file(&file_path);
+                    let mut negotiated = Negotiated::new(request, Some(&content_type), r.get_size());
+                    // Transcluded files have to be put together by the
+                    // server, so only others are compressed in advance
+                    if r.get_size().is_some() {
+                        negotiated = negotiated.with_precompressed(request, &file_path, &metadata);
+                        // Parts are sent of the file as it is
+                        if headers.contains("range") {
+                            negotiated.identity();
+                        }
+                    }
+                    // Transcluded files also change with the files they
+                    // include, so they only get a weak tag
+                    let validators = match &negotiated.precompressed {
+                        Some((_, metadata)) => Validators::of_file(metadata, false),
+                        None => Validators::of_file(&metadata, r.get_size().is_none())
+                    }.with_encoding(negotiated.encoding);
+                    if respond_to_conditions(&validators, &negotiated, request, client)? {
+                        return Ok(());
+                    }
+                    let response = validators.add_to(Response::ok().header("Content-Type", content_type.clone()));
+                    let response = negotiated.add_to(response).header("Cache-Control", "max-age=30");
+                    match (&negotiated.precompressed, negotiated.encoding) {
+                        (Some((path, metadata)), _) => {
+                            client.send(response.body_file(File::open(path)?, Some(metadata.len() as usize)))?;
+                        },
+                        // Compressed by the worker rather than the event
+                        // loop, which serves other connections meanwhile
+                        (None, Some(encoding)) => {
+                            client.send(response.body_stream(Compressed::new(r, encoding)?))?;
+                        },
+                        (None, None) => send_file(r, &validators, &content_type, response, request, client)?
+                    }
                 },
                 Err(_) => {
-                    client.respond("500 Internal Server Error", error_pages::ERROR_500.as_bytes(), &vec![])?;
+                    client.send(error_response(StatusCode::InternalServerError, error_pages::ERROR_500))?;
+                }
+            }
+        }
+    } else {
+        client.send(error_response(StatusCode::NotFound, error_pages::ERROR_404))?;
+    }
+    Ok(())
+}
+
+
+// Respond with "304 Not Modified" or "412 Precondition Failed" if the
+// preconditions of a conditional request call for it. Returns whether a
+// response was sent.
+fn respond_to_conditions(
+    validators: &Validators, negotiated: &Negotiated, request: &Request, client: &mut Client) -> Result<bool>
+{
+    match validators.check(request) {
+        Some(StatusCode::NotModified) => {
+            let response = validators.add_to(Response::new(StatusCode::NotModified));
+            client.send(negotiated.add_to(response).header("Cache-Control", "max-age=30"))?;
+        },
+        Some(status) => {
+            client.send(error_response(status, error_pages::ERROR_412))?;
+        },
+        None => return Ok(false)
+    }
+    Ok(true)
+}
+
+
+// Send a file, or the parts of it the client asked for
+fn send_file(
+    r: FileReader, validators: &Validators, content_type: &str,
+    response: Response<'static>, request: &Request, client: &mut Client) -> Result<()>
+{
+    let headers = request.headers();
+    match r.get_size() {
+        Some(size) => {
+            // Parts are only sent if the client's copy of the rest is still
+            // current
+            let ranges = headers.get("range")
+                .filter(|_| request.method() == Method::GET)
+                .filter(|_| headers.get("if-range").is_none_or(|v| validators.matches_if_range(v)))
+                .and_then(|range| ranges::parse(range, size as u64));
+            let response = response.header("Accept-Ranges", "bytes");
+            match ranges {
+                Some(ranges) => send_ranges(r.into_file()?, ranges, size, content_type, response, client)?,
+                None => {
+                    client.send(response.body_file(r, Some(size)))?;
                 }
             }
+        },
+        // Transcluded files are put together while they are sent, so they
+        // are always sent whole
+        None => {
+            client.send(response.body_file(r, None))?;
         }
+    }
+    Ok(())
+}
+
+
+// Respond to a request for parts of a file of the given size, with the
+// response for the whole file
+fn send_ranges(
+    mut file: File, ranges: Ranges, size: usize, content_type: &str,
+    mut response: Response<'static>, client: &mut Client) -> Result<()>
+{
+    let size = size as u64;
+    let ranges = match ranges {
+        Ranges::Satisfiable(ranges) => ranges,
+        Ranges::Unsatisfiable => {
+            client.send(error_response(StatusCode::RangeNotSatisfiable, error_pages::ERROR_416)
+                .header("Content-Range", format!("bytes */{}", size)))?;
+            return Ok(());
+        }
+    };
+
+    response.set_status(StatusCode::PartialContent);
+    if let [range] = &ranges[..] {
+        file.seek(SeekFrom::Start(range.start))?;
+        let length = range.end - range.start;
+        client.send(response
+            .header("Content-Range", ranges::content_range(range, size))
+            .body_file(file.take(length), Some(length as usize)))?;
     } else {
-        client.respond("404 Not Found", error_pages::ERROR_404.as_bytes(), &vec![])?;
+        let (body, content_type, length) = ByteRanges::new(file, &ranges, content_type, size);
+        client.send(response
+            .header("Content-Type", content_type)
+            .body_file(body, Some(length)))?;
     }
     Ok(())
 }
 
 
 // Helper function to respond to POST requests
-fn handle_post(file_path: &PathBuf, data: &mut Option<FormData>, mut client: Client) -> Result<()> {
+fn handle_post(file_path: &Path, headers: &Headers, client: &mut Client) -> Result<()> {
     let mut file_path = file_path.to_owned();
 
     // Unlike GET requests, POST requests MUST be handled by an executable
@@ -243,9 +819,28 @@ fn handle_post(file_path: &PathBuf, data: &mut Option<FormData>, mut client: Cli
     }
     // If the executable path does not exist (or the points to a directory), exit.
     if !file_path.exists() || !file_path.is_file() {
-        client.respond("404 Not Found", error_pages::ERROR_404.as_bytes(), &vec![])?;
+        client.send(error_response(StatusCode::NotFound, error_pages::ERROR_404))?;
         return Ok(());
     }
+    let mut data = match client.form_data(headers) {
+        Ok(data) => data,
+        Err(e) => return respond_to_body_error(e, client)
+    };
+
+    // Uploaded files are removed once the executable is done with them
+    let mut uploads = Uploads(Vec::new());
+    if let Some(FormData::Multipart(multipart)) = data.as_mut() {
+        let mut vars = QueryData::new();
+        if let Err(e) = read_multipart(multipart, &mut vars, &mut uploads) {
+            if RequestError::from_io_error(&e).is_none() {
+                client.send(error_response(StatusCode::InternalServerError, error_pages::ERROR_500))?;
+                return Err(e);
+            }
+            return respond_to_body_error(e, client);
+        }
+        data = Some(FormData::KeyVal(vars));
+    }
+
     let executable_path = file_path.as_os_str();
     let mut command = Command::new(executable_path);
     command.arg(&file_path)
@@ -254,53 +849,106 @@ fn handle_post(file_path: &PathBuf, data: &mut Option<FormData>, mut client: Cli
     // Different data will be fed to the executable depending on how the form
     // was encoded.
     match data.as_mut() {
-        // URL encoded form
+        // URL encoded or multipart form
         Some(FormData::KeyVal(vars)) => {
             let allowed_variables_file = get_adjacent_allowed_variables_file(&file_path)?;
             let allowed_variables = get_allowed_variables(allowed_variables_file)?;
-            filter_env_variables(vars, &allowed_variables);
+            let mut vars = first_values(vars);
+            filter_env_variables(&mut vars, &allowed_variables);
             command.envs(vars);
         },
         // Plaintext form
         Some(FormData::Text(text)) => {
             command.arg(text);
         },
-        // Multipart form
-        Some(FormData::Stream(_)) => {
-            command.stdin(Stdio::piped());
-        },
         _ => {}
     }
-    let mut child_process = command.spawn()?;
-    if let Some(mut stdin) = child_process.stdin.take() {
-        if let Some(FormData::Stream(mut reader)) = data.take() {
-            let mut buffer = [0; 4096];
-            while let Ok(bytes_read) = reader.read(&mut buffer) {
-                if bytes_read == 0 { break; }
-                stdin.write_all(&buffer)
-                    .expect("Writing to stdin");
+    let mut child_process = RunningChild::spawn(&mut command)?;
+    client.send(Response::ok().body_stream(child_process.stdout.take().expect("Capturing stdout")))?;
+    // Wait for the executable before its uploads are removed
+    if !uploads.0.is_empty() {
+        child_process.wait()?;
+    }
+    Ok(())
+}
+
+
+// Temporary files holding the files uploaded with a form
+struct Uploads(Vec<PathBuf>);
+
+impl Uploads {
+    // Copy an uploaded file to a new temporary file, returning its path. The
+    // temporary directory is shared, so only this user may read the file.
+    fn create(&mut self, file: &mut impl Read) -> Result<PathBuf> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        loop {
+            let path = env::temp_dir().join(format!("user_sites-{}-{}",
+                process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+            let mut temp_file = match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path) {
+                Ok(temp_file) => temp_file,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e)
+            };
+            self.0.push(path.clone());
+            io::copy(file, &mut temp_file)?;
+            return Ok(path);
+        }
+    }
+}
+
+impl Drop for Uploads {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            drop(fs::remove_file(path));
+        }
+    }
+}
+
+// Read the parts of a multipart form. Simple fields are stored in vars; files
+// are stored in temporary files, with the field's variable holding the path
+// of the temporary file and "<name>_filename" the original file name.
+fn read_multipart<R: Read>(multipart: &mut Multipart<R>, vars: &mut QueryData, uploads: &mut Uploads) -> Result<()> {
+    while let Some(mut part) = multipart.next_part()? {
+        let name = match part.name() {
+            Some(name) => name.to_owned(),
+            None => continue
+        };
+        match part.filename().map(|f| f.to_owned()) {
+            Some(filename) => {
+                let path = uploads.create(&mut part)?;
+                vars.append(name.clone(), path.to_string_lossy());
+                vars.append(format!("{}_filename", name), filename);
+            },
+            None => {
+                let mut value = Vec::new();
+                part.read_to_end(&mut value)?;
+                vars.append(name, String::from_utf8_lossy(&value));
             }
         }
     }
-    client.respond_ok_chunked(child_process.stdout.expect("Capturing stdout"), usize::MAX)?;
     Ok(())
 }
 
 
+// Turn key-value pairs into variables for an executable. Environment
+// variables can't be repeated, so only the first value of each key is used.
+fn first_values(pairs: &QueryData) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    for (k, v) in pairs.iter() {
+        vars.entry(k.to_owned()).or_insert_with(|| v.to_owned());
+    }
+    vars
+}
+
 // Filter out variable definitions that are already present
 fn filter_env_variables(vars: &mut HashMap<String, String>, allowed_variables: &HashSet<String>) {
-    for var in vars.keys().map(|k| k.to_owned()).collect::<Vec<String>>() {
-        // Remove var if it is already defined or all caps
-        if let Ok(var) = urlencoding::decode(&var) {
-            let var = var.to_string();
-            if env::var(&var).is_ok()
-                || var == var.to_uppercase()
-                || !allowed_variables.contains(&var)
-            {
-                vars.remove(&var);
-            }
-        }
-    }
+    // Remove var if it is already defined or all caps
+    vars.retain(|var, _| {
+        env::var(var).is_err()
+            && var != &var.to_uppercase()
+            && allowed_variables.contains(var)
+    });
 }
 
 // Get a reader for a file named "allowed_varibles" adjacent to the file located
@@ -327,3 +975,594 @@ where R: BufRead {
 
     Ok(allowed_variables)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::metadata;
+    use micro_http_server::{ClientAddr, TrustedProxies};
+    use httpdate::fmt_http_date;
+
+    // The pages and executables the handlers are tested with
+    fn www() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("www")
+    }
+
+    // Read a request from `input`, pass it to `handler` and return everything
+    // that was sent in response
+    fn respond<F>(input: &str, handler: F) -> String
+    where F: FnOnce(&Request, &mut Client) -> Result<()>
+    {
+        String::from_utf8(respond_bytes(input, handler)).unwrap()
+    }
+
+    // Like `respond`, for responses which aren't text
+    fn respond_bytes<F>(input: &str, handler: F) -> Vec<u8>
+    where F: FnOnce(&Request, &mut Client) -> Result<()>
+    {
+        let (mut client, capture) = Client::in_memory(input);
+        assert!(client.next_request().unwrap());
+        let request = client.request_mut().take().expect("Invalid request");
+        handler(&request, &mut client).unwrap();
+        capture.bytes()
+    }
+
+    // Where a request from this host to a server reached at "/" comes from
+    fn origin(request: &Request) -> Origin {
+        let addr = ClientAddr::from(SocketAddr::from(([127, 0, 0, 1], 4711)));
+        Origin::new(request, &addr, &addr, &Forwarding::default())
+    }
+
+    #[test]
+    fn get_file() {
+        let file_path = www().join("a.txt");
+        let response = respond("GET /a.txt HTTP/1.1\r\n\r\n", |r, c| handle_get(&file_path, r, &origin(r), c));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Length: 6\r\n"));
+        assert!(response.contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"));
+        assert!(response.contains("\r\nCache-Control: max-age=30\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello\n"));
+
+        // Files which haven't changed since the client's copy aren't sent again
+        let modified = fmt_http_date(metadata(&file_path).unwrap().modified().unwrap());
+        let input = format!("GET /a.txt HTTP/1.1\r\nIf-Modified-Since: {}\r\n\r\n", modified);
+        let response = respond(&input, |r, c| handle_get(&file_path, r, &origin(r), c));
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn get_ranges() {
+        let file_path = www().join("a.txt");
+        let get = |headers: &str| {
+            let input = format!("GET /a.txt HTTP/1.1\r\n{}\r\n", headers);
+            respond(&input, |r, c| handle_get(&file_path, r, &origin(r), c))
+        };
+
+        let response = get("");
+        assert!(response.contains("\r\nAccept-Ranges: bytes\r\n"));
+
+        let response = get("Range: bytes=1-3\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\nContent-Length: 3\r\n"));
+        assert!(response.contains("\r\nContent-Range: bytes 1-3/6\r\n"));
+        assert!(response.ends_with("\r\n\r\nell"));
+
+        let response = get("Range: bytes=-2\r\n");
+        assert!(response.contains("\r\nContent-Range: bytes 4-5/6\r\n"));
+        assert!(response.ends_with("\r\n\r\no\n"));
+
+        // Several ranges are sent as parts of a multipart body, where
+        // overlapping ones are merged
+        let response = get("Range: bytes=4-, 0-0,5-\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        let boundary = response.split("\r\nContent-Type: multipart/byteranges; boundary=").nth(1).unwrap();
+        let boundary = &boundary[..boundary.find("\r\n").unwrap()];
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        assert_eq!(body, format!(concat!(
+            "\r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-0/6\r\n\r\nh",
+            "\r\n--{b}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 4-5/6\r\n\r\no\n",
+            "\r\n--{b}--\r\n"), b = boundary));
+        assert!(response.contains(&format!("\r\nContent-Length: {}\r\n", body.len())));
+
+        let response = get("Range: bytes=6-\r\n");
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(response.contains("\r\nContent-Range: bytes */6\r\n"));
+
+        // Otherwise, the whole file is sent
+        let modified = fmt_http_date(metadata(&file_path).unwrap().modified().unwrap());
+        for headers in &[
+            "Range: bytes=4-2\r\n",
+            "Range: lines=1-2\r\n",
+            "Range: bytes=1-\r\nIf-Range: Thu, 01 Jan 1970 00:00:00 GMT\r\n"
+        ] {
+            assert!(get(headers).ends_with("\r\n\r\nhello\n"), "{}", headers);
+        }
+        let headers = format!("Range: bytes=1-\r\nIf-Range: {}\r\n", modified);
+        assert!(get(&headers).ends_with("\r\n\r\nello\n"));
+
+        // Transcluded files can't be sent in parts
+        let file_path = www().join("page.html");
+        let response = respond("GET /page.html HTTP/1.1\r\nRange: bytes=0-1\r\n\r\n", |r, c| handle_get(&file_path, r, &origin(r), c));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!response.contains("Accept-Ranges"));
+        assert!(response.contains("<p>hello\n</p>\n"));
+    }
+
+    #[test]
+    fn get_conditional() {
+        let get = |file: &str, headers: &str| {
+            let file_path = www().join(file);
+            let input = format!("GET /{} HTTP/1.1\r\n{}\r\n", file, headers);
+            respond(&input, |r, c| handle_get(&file_path, r, &origin(r), c))
+        };
+        let etag_of = |response: &str| {
+            let etag = response.split("\r\nETag: ").nth(1).unwrap();
+            etag[..etag.find("\r\n").unwrap()].to_owned()
+        };
+
+        let etag = etag_of(&get("a.txt", ""));
+        assert!(etag.starts_with('"'));
+        for headers in &[
+            format!("If-None-Match: {}\r\n", etag),
+            format!("If-None-Match: \"x\", W/{}\r\n", etag),
+            "If-None-Match: *\r\n".to_owned(),
+            "If-Modified-Since: Fri, 01 Jan 2100 00:00:00 GMT\r\n".to_owned(),
+            format!("If-Match: {}\r\nIf-None-Match: {}\r\n", etag, etag)
+        ] {
+            let response = get("a.txt", headers);
+            assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", headers);
+            assert!(response.contains(&format!("\r\nETag: {}\r\n", etag)), "{}", headers);
+        }
+        for headers in &[
+            "If-None-Match: \"x\"\r\n",
+            // If-None-Match takes precedence over If-Modified-Since
+            "If-None-Match: \"x\"\r\nIf-Modified-Since: Fri, 01 Jan 2100 00:00:00 GMT\r\n",
+            "If-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n",
+            "If-Modified-Since: yesterday\r\n",
+            "If-Match: *\r\n",
+            "If-Unmodified-Since: Fri, 01 Jan 2100 00:00:00 GMT\r\n"
+        ] {
+            assert!(get("a.txt", headers).ends_with("\r\n\r\nhello\n"), "{}", headers);
+        }
+        for headers in &[
+            "If-Match: \"x\"\r\n".to_owned(),
+            format!("If-Match: W/{}\r\n", etag),
+            "If-Unmodified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n".to_owned()
+        ] {
+            let response = get("a.txt", headers);
+            assert!(response.starts_with("HTTP/1.1 412 Precondition Failed\r\n"), "{}", headers);
+        }
+
+        // Ranges are only sent for the same version, which a weak tag can't tell
+        let response = get("a.txt", &format!("Range: bytes=1-\r\nIf-Range: {}\r\n", etag));
+        assert!(response.ends_with("\r\n\r\nello\n"));
+        let response = get("a.txt", &format!("Range: bytes=1-\r\nIf-Range: W/{}\r\n", etag));
+        assert!(response.ends_with("\r\n\r\nhello\n"));
+
+        // Transcluded pages and auto indexes only get weak tags
+        for file in &["page.html", "dir"] {
+            let etag = etag_of(&get(file, ""));
+            assert!(etag.starts_with("W/\""), "{}", file);
+            let response = get(file, &format!("If-None-Match: {}\r\n", etag));
+            assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"), "{}", file);
+            let response = get(file, &format!("If-Match: {}\r\n", etag));
+            assert!(response.starts_with("HTTP/1.1 412 Precondition Failed\r\n"), "{}", file);
+        }
+    }
+
+    #[test]
+    fn get_compressed() {
+        // The head of the response and its body
+        let get = |path: &str, headers: &str| {
+            let input = format!("GET /{} HTTP/1.0\r\n{}\r\n", path, headers);
+            let response = respond_bytes(&input, |r, c| handle_get(&www().join(path), r, &origin(r), c));
+            let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            (String::from_utf8(response[..end + 2].to_vec()).unwrap(), response[end + 4..].to_vec())
+        };
+        let decompress = |body: &[u8], encoding: &str| {
+            let mut data = Vec::new();
+            match encoding {
+                "gzip" => flate2::read::GzDecoder::new(body).read_to_end(&mut data),
+                "br" => brotli::Decompressor::new(body, 4096).read_to_end(&mut data),
+                _ => zstd::Decoder::new(body).unwrap().read_to_end(&mut data)
+            }.unwrap();
+            data
+        };
+
+        let (head, plain) = get("style.css", "");
+        assert!(head.contains("\r\nVary: Accept-Encoding\r\n"));
+        assert!(!head.contains("Content-Encoding"));
+        for &(accept, encoding) in &[
+            ("gzip", "gzip"),
+            ("gzip, deflate, br, zstd", "br"),
+            ("zstd;q=1.0, br;q=0.9", "zstd"),
+            ("gzip;q=0, *", "br")
+        ] {
+            let (head, body) = get("style.css", &format!("Accept-Encoding: {}\r\n", accept));
+            assert!(head.contains(&format!("\r\nContent-Encoding: {}\r\n", encoding)), "{}", accept);
+            assert!(head.contains("\r\nVary: Accept-Encoding\r\n"), "{}", accept);
+            assert!(body.len() < plain.len(), "{}", accept);
+            assert_eq!(decompress(&body, encoding), plain, "{}", accept);
+        }
+        for accept in &["identity", "gzip;q=0", "br;q=0, *;q=0", "gzip;q=0.5, identity", "compress"] {
+            let (head, body) = get("style.css", &format!("Accept-Encoding: {}\r\n", accept));
+            assert!(!head.contains("Content-Encoding"), "{}", accept);
+            assert_eq!(body, plain, "{}", accept);
+        }
+
+        // Small files and images are always sent as they are
+        for path in &["a.txt", "pixel"] {
+            let (head, _) = get(path, "Accept-Encoding: gzip\r\n");
+            assert!(!head.contains("Vary"), "{}", path);
+            assert!(!head.contains("Content-Encoding"), "{}", path);
+        }
+
+        // Parts are sent of the file as it is
+        let (head, body) = get("style.css", "Accept-Encoding: gzip\r\nRange: bytes=0-9\r\n");
+        assert!(head.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(!head.contains("Content-Encoding"));
+        assert_eq!(body, &plain[..10]);
+
+        // Compressed versions have tags of their own
+        let (head, _) = get("style.css", "Accept-Encoding: gzip\r\n");
+        let etag = head.split("\r\nETag: ").nth(1).unwrap();
+        let etag = &etag[..etag.find("\r\n").unwrap()];
+        assert!(etag.ends_with("-gzip\""));
+        let (head, _) = get("style.css", &format!("Accept-Encoding: gzip\r\nIf-None-Match: {}\r\n", etag));
+        assert!(head.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(head.contains("\r\nVary: Accept-Encoding\r\n"));
+        let (head, _) = get("style.css", &format!("If-None-Match: {}\r\n", etag));
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        // Transcluded pages, auto indexes and the output of executables are
+        // compressed as well
+        for &(path, encoding) in &[("page.html", "gzip"), ("", "br"), ("exe", "zstd")] {
+            let (_, plain) = get(path, "");
+            let (head, body) = get(path, &format!("Accept-Encoding: {}\r\n", encoding));
+            assert!(head.contains(&format!("\r\nContent-Encoding: {}\r\n", encoding)), "{}", path);
+            assert_eq!(decompress(&body, encoding), plain, "{}", path);
+        }
+    }
+
+    #[test]
+    fn get_precompressed() {
+        let dir = env::temp_dir().join(format!("user_sites-precompressed-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let plain = fs::read(www().join("style.css")).unwrap();
+        fs::write(dir.join("style.css"), &plain).unwrap();
+        fs::write(dir.join("style.css.gz"), b"gzipped").unwrap();
+        fs::write(dir.join("style.css.br"), b"brotli").unwrap();
+        fs::write(dir.join("lonely.gz"), b"gzipped").unwrap();
+
+        let get = |path: &str, headers: &str| {
+            let input = format!("GET /{} HTTP/1.1\r\n{}\r\n", path, headers);
+            respond_bytes(&input, |r, c| handle_get(&dir.join(path), r, &origin(r), c))
+        };
+        let response = String::from_utf8(get("style.css", "Accept-Encoding: gzip\r\n")).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Length: 7\r\n"));
+        assert!(response.contains("\r\nContent-Type: text/css; charset=utf-8\r\n"));
+        assert!(response.contains("\r\nContent-Encoding: gzip\r\n"));
+        assert!(response.contains("\r\nVary: Accept-Encoding\r\n"));
+        assert!(response.ends_with("\r\n\r\ngzipped"));
+        let response = String::from_utf8(get("style.css", "Accept-Encoding: gzip, br\r\n")).unwrap();
+        assert!(response.ends_with("\r\n\r\nbrotli"));
+        // Precompressed files are preferred to compressing the file
+        let response = String::from_utf8(get("style.css", "Accept-Encoding: zstd, gzip;q=0.5\r\n")).unwrap();
+        assert!(response.ends_with("\r\n\r\ngzipped"));
+
+        // Otherwise, the file is sent as usual
+        assert!(get("style.css", "").ends_with(&plain));
+        assert!(get("style.css", "Accept-Encoding: gzip\r\nRange: bytes=0-\r\n").ends_with(&plain));
+        let response = get("style.css", "Accept-Encoding: zstd\r\n");
+        assert!(String::from_utf8_lossy(&response).contains("\r\nContent-Encoding: zstd\r\n"));
+
+        // Precompressed files older than the file are outdated
+        let modified = metadata(dir.join("style.css")).unwrap().modified().unwrap();
+        File::options().write(true).open(dir.join("style.css.br")).unwrap()
+            .set_modified(modified - Duration::from_secs(1)).unwrap();
+        let response = String::from_utf8(get("style.css", "Accept-Encoding: br, gzip\r\n")).unwrap();
+        assert!(response.ends_with("\r\n\r\ngzipped"));
+
+        // They aren't listed along with the file
+        let response = String::from_utf8(get("", "")).unwrap();
+        assert!(response.contains("style.css"));
+        assert!(response.contains("lonely.gz"));
+        assert!(!response.contains("style.css.gz"));
+        assert!(!response.contains("style.css.br"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn get_not_found() {
+        for path in &["missing.txt", "exe/allowed_variables", "form/form_executable"] {
+            let response = respond("GET / HTTP/1.1\r\n\r\n", |r, c| handle_get(&www().join(path), r, &origin(r), c));
+            assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", path);
+            assert!(response.contains("\r\nContent-Type: text/html; charset=utf-8\r\n"), "{}", path);
+        }
+    }
+
+    #[test]
+    fn get_auto_index() {
+        let response = respond("GET /dir/ HTTP/1.1\r\n\r\n", |r, c| handle_get(&www().join("dir"), r, &origin(r), c));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\nContent-Type: text/html; charset=utf-8\r\n"));
+        assert!(response.contains("b.txt"));
+        assert!(response.contains("<base href=\"/dir/\"/>"));
+
+        // Links start with the path the server is reached at
+        let forwarding = Forwarding { base_path: "/users".to_owned(), ..Forwarding::default() };
+        let response = respond("GET /dir/ HTTP/1.1\r\n\r\n", |r, c| {
+            let origin = Origin::new(r, c.addr(), c.peer_addr(), &forwarding);
+            handle_get(&www().join("dir"), r, &origin, c)
+        });
+        assert!(response.contains("<base href=\"/users/dir/\"/>"));
+    }
+
+    #[test]
+    fn forwarded() {
+        let forwarding = Forwarding {
+            trusted: TrustedProxies { addrs: vec!["127.0.0.1".parse().unwrap()], unix: false },
+            base_path: "/users".to_owned()
+        };
+        let proxy = ClientAddr::from(SocketAddr::from(([127, 0, 0, 1], 4711)));
+        let other = ClientAddr::from(SocketAddr::from(([192, 0, 2, 7], 4711)));
+        let origin = |input: &str, peer_addr: &ClientAddr| {
+            let (mut client, _) = Client::in_memory(input);
+            assert!(client.next_request().unwrap());
+            Origin::new(client.request().as_ref().unwrap(), &proxy, peer_addr, &forwarding)
+        };
+
+        let input = concat!(
+            "GET / HTTP/1.1\r\n",
+            "X-Forwarded-For: 198.51.100.1, 192.0.2.1\r\n",
+            "X-Forwarded-Proto: https\r\n",
+            "X-Forwarded-Host: example.com\r\n",
+            "X-Forwarded-Prefix: /~\r\n\r\n");
+        let forwarded = origin(input, &proxy);
+        assert_eq!("192.0.2.1", forwarded.client);
+        assert_eq!("https://example.com/~/alice/", forwarded.url("/alice/"));
+
+        // Only trusted proxies are listened to
+        let direct = origin(input, &other);
+        assert_eq!("127.0.0.1:4711", direct.client);
+        assert_eq!("/users/alice/", direct.url("/alice/"));
+
+        // Forwarded takes precedence, and its last element is the proxy's
+        let input = concat!(
+            "GET / HTTP/1.1\r\n",
+            "X-Forwarded-Host: example.com\r\n",
+            "Forwarded: for=198.51.100.1;host=evil.example\r\n",
+            "Forwarded: for=\"[2001:db8::1]:4711\";proto=https;host=example.org\r\n\r\n");
+        let forwarded = origin(input, &proxy);
+        assert_eq!("[2001:db8::1]:4711", forwarded.client);
+        assert_eq!("https://example.org/users/alice/", forwarded.url("/alice/"));
+
+        // Without the scheme, and with an invalid prefix, the URL stays relative
+        let input = "GET / HTTP/1.1\r\nX-Forwarded-Host: example.com\r\nX-Forwarded-Prefix: //evil.example\r\n\r\n";
+        assert_eq!("/users/alice/", origin(input, &proxy).url("/alice/"));
+    }
+
+    #[test]
+    fn redirect_to_directory() {
+        // "//evil.example" is the index of all users, whose redirect must
+        // not lead to another host
+        let input = "GET //evil.example?a=1 HTTP/1.1\r\n\r\n";
+        let response = respond(input, |r, c| handle_valid_request(r, &origin(r), c));
+        assert!(response.starts_with("HTTP/1.1 302 Found\r\n"));
+        assert!(response.contains("\r\nLocation: /evil.example/?a=1\r\n"));
+    }
+
+    #[test]
+    fn content_types() {
+        let mut mime_types = MimeTypes::default();
+        assert_eq!("text/plain; charset=utf-8", mime_types.of_file(&www().join("a.txt")));
+        assert_eq!("text/html; charset=utf-8", mime_types.of_file(Path::new("/missing/page.HTML")));
+        // Files without a known extension are recognized by their contents
+        assert_eq!("text/plain; charset=utf-8", mime_types.of_file(&www().join("notes")));
+        assert_eq!("image/png", mime_types.of_file(&www().join("pixel")));
+        assert_eq!("application/octet-stream", mime_types.of_file(Path::new("/missing/data.bin")));
+
+        mime_types.load(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("mime.types")).unwrap();
+        assert_eq!("application/x-text", mime_types.of_file(&www().join("a.txt")));
+        assert_eq!("text/x-readme; charset=utf-8", mime_types.of_file(Path::new("/missing/a.readme")));
+    }
+
+    #[test]
+    fn handler_error() {
+        // An executable without allowed_variables can't be run
+        let dir = env::temp_dir().join(format!("user_sites-handler-error-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy(www().join("exe").join("index_executable"), dir.join("index_executable")).unwrap();
+
+        let (mut client, capture) = Client::in_memory("GET /exe/ HTTP/1.1\r\n\r\nGET /a.txt HTTP/1.1\r\n\r\n");
+        assert!(client.next_request().unwrap());
+        let request = client.request_mut().take().unwrap();
+        let origin = origin(&request);
+        let e = handle_get(&dir, &request, &origin, &mut client).unwrap_err();
+        respond_to_handler_error(e, &origin, &mut client);
+        let response = String::from_utf8(capture.bytes()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+
+        // The connection is still good for the next request
+        assert!(client.keep_alive());
+        assert!(client.next_request().unwrap());
+        assert_eq!(client.request().as_ref().unwrap().path(), "/a.txt");
+
+        // Once the response was started, the connection is closed instead
+        client.send(Response::ok().body_stream(io::empty())).unwrap();
+        respond_to_handler_error(io::Error::other("Broken"), &origin, &mut client);
+        assert!(!client.keep_alive());
+
+        // HEAD fails the same way, although it wouldn't run the executable
+        let (mut client, capture) = Client::in_memory("HEAD /exe/ HTTP/1.1\r\n\r\n");
+        assert!(client.next_request().unwrap());
+        let request = client.request_mut().take().unwrap();
+        let e = handle_get(&dir, &request, &origin, &mut client).unwrap_err();
+        respond_to_handler_error(e, &origin, &mut client);
+        assert!(capture.bytes().starts_with(b"HTTP/1.1 500 Internal Server Error\r\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn get_index_executable() {
+        let input = "GET /exe/?name=a+b&secret=c HTTP/1.0\r\n\r\n";
+        let response = respond(input, |r, c| handle_get(&www().join("exe"), r, &origin(r), c));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\nCache-Control: no-cache\r\n"));
+        // Only allowed variables are passed on, but the query string is complete
+        assert!(response.ends_with("\r\n\r\nname=a b secret= query=name=a+b&secret=c\n"));
+
+        // HEAD requests don't run the executable
+        let input = "HEAD /exe/?name=a HTTP/1.1\r\n\r\n";
+        let response = respond(input, |r, c| handle_get(&www().join("exe"), r, &origin(r), c));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn post_url_encoded() {
+        let input = concat!(
+            "POST /form/ HTTP/1.0\r\n",
+            "Content-Type: application/x-www-form-urlencoded\r\n",
+            "Content-Length: 19\r\n\r\n",
+            "name=a+b&secret=%21");
+        let response = respond(input, |r, c| handle_post(&www().join("form"), r.headers(), c));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nname=a b up= up_filename= text=\n"));
+    }
+
+    #[test]
+    fn post_text() {
+        let input = concat!(
+            "POST /form/form_executable HTTP/1.0\r\n",
+            "Content-Type: text/plain\r\n",
+            "Content-Length: 5\r\n\r\n",
+            "hello");
+        let file_path = www().join("form").join("form_executable");
+        let response = respond(input, |r, c| handle_post(&file_path, r.headers(), c));
+        assert!(response.ends_with("\r\n\r\nname= up= up_filename= text=hello\n"));
+    }
+
+    #[test]
+    fn post_multipart() {
+        let body = concat!(
+            "--XyZ\r\n",
+            "Content-Disposition: form-data; name=\"name\"\r\n\r\n",
+            "a\r\n",
+            "--XyZ\r\n",
+            "Content-Disposition: form-data; name=\"up\"; filename=\"cat.txt\"\r\n",
+            "Content-Type: text/plain\r\n\r\n",
+            "Cats are nice.\r\n",
+            "--XyZ--\r\n");
+        let input = format!(concat!(
+            "POST /form/ HTTP/1.0\r\n",
+            "Content-Type: multipart/form-data; boundary=XyZ\r\n",
+            "Content-Length: {}\r\n\r\n{}"), body.len(), body);
+        let response = respond(&input, |r, c| handle_post(&www().join("form"), r.headers(), c));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(" up_filename=cat.txt text=\nCats are nice."));
+
+        // The uploaded file is removed once the executable is done with it
+        let output = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        let upload = output.split(' ').find_map(|var| var.strip_prefix("up=")).unwrap();
+        assert!(!upload.is_empty());
+        assert!(!Path::new(upload).exists());
+    }
+
+    #[test]
+    fn ranges_of_replaced_file() {
+        let dir = env::temp_dir().join(format!("user_sites-replaced-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "Cats are nice.").unwrap();
+        fs::write(dir.join("b.txt"), "Dogs are loud!").unwrap();
+
+        // The parts are read from the file whose size was sent, even once
+        // another one took its place
+        let r = FileReader::new(dir.join("a.txt")).unwrap();
+        let validators = Validators::of_file(&r.metadata().unwrap(), false);
+        fs::rename(dir.join("b.txt"), dir.join("a.txt")).unwrap();
+        let input = "GET /a.txt HTTP/1.1\r\nRange: bytes=0-3\r\n\r\n";
+        let response = respond(input, |request, c| {
+            send_file(r, &validators, "text/plain", Response::ok(), request, c)
+        });
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.ends_with("\r\n\r\nCats"));
+    }
+
+    #[test]
+    fn busy_server() {
+        use std::io::Write;
+        use std::net::TcpStream;
+        use std::sync::{Mutex, mpsc};
+
+        let server = Arc::new(MicroHTTP::new("127.0.0.1:0").unwrap());
+        let addr = match &server.listeners()[0] {
+            Listener::Tcp(listener) => listener.local_addr().unwrap(),
+            _ => unreachable!()
+        };
+        // The only worker tells when it started on a client and waits to be
+        // told to answer it, while one more client may wait for it
+        let (started, has_started) = mpsc::channel();
+        let (proceed, may_proceed) = mpsc::channel();
+        let may_proceed = Mutex::new(may_proceed);
+        let pool = Arc::new(WorkerPool::new(1, 1, move |mut client: Client| {
+            started.send(()).unwrap();
+            may_proceed.lock().unwrap().recv().unwrap();
+            client.next_request().unwrap();
+            client.close_after_response();
+            client.send(Response::ok().body("Cats are nice.".as_bytes())).unwrap();
+        }));
+        let accepting = {
+            let (server, pool) = (server.clone(), pool.clone());
+            thread::spawn(move || accept_clients(&server, &pool))
+        };
+
+        let connect = || {
+            let mut connection = TcpStream::connect(addr).unwrap();
+            connection.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            connection
+        };
+        let read = |mut connection: TcpStream| {
+            let mut response = String::new();
+            connection.read_to_string(&mut response).unwrap();
+            response
+        };
+        let working = connect();
+        has_started.recv().unwrap();
+        let queued = connect();
+        let response = read(connect());
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("\r\nRetry-After: 5\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+
+        // The server keeps accepting clients once workers are available
+        for _ in 0..3 {
+            proceed.send(()).unwrap();
+        }
+        assert!(read(working).starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(read(queued).starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(read(connect()).starts_with("HTTP/1.1 200 OK\r\n"));
+
+        server.stop_handle().stop();
+        accepting.join().unwrap();
+    }
+
+    #[test]
+    fn upload_permissions() {
+        let mut uploads = Uploads(Vec::new());
+        let path = uploads.create(&mut "Cats are nice.".as_bytes()).unwrap();
+        assert_eq!(metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        drop(uploads);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn post_not_found() {
+        let input = "POST /exe/ HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let response = respond(input, |r, c| handle_post(&www().join("exe"), r.headers(), c));
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,202 @@
+// The media types of served files, which are looked up by their extension
+// and otherwise guessed from their first bytes
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Result};
+use std::path::Path;
+use std::str;
+use std::sync::OnceLock;
+
+
+// The types of pages the server generates, like auto indexes and error pages
+pub const HTML: &str = "text/html; charset=utf-8";
+
+const OCTET_STREAM: &str = "application/octet-stream";
+
+// How much of a file is looked at to guess its type
+const SNIFF_LENGTH: usize = 512;
+
+// Types for common extensions, which a mime.types file may override
+const BUILTIN: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("xhtml", "application/xhtml+xml"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("rss", "application/rss+xml"),
+    ("atom", "application/atom+xml"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("ics", "text/calendar"),
+    ("vtt", "text/vtt"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("bmp", "image/bmp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("m4a", "audio/mp4"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("xz", "application/x-xz"),
+    ("zst", "application/zstd"),
+    ("7z", "application/x-7z-compressed"),
+    ("wasm", "application/wasm")
+];
+
+// Signatures at the start of files, and the types they belong to
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x00asm", "application/wasm"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+    (b"OggS", "application/ogg"),
+    (b"ID3", "audio/mpeg"),
+    (b"\x1a\x45\xdf\xa3", "video/webm")
+];
+
+// Set once when the server starts
+static MIME_TYPES: OnceLock<MimeTypes> = OnceLock::new();
+
+
+pub struct MimeTypes(HashMap<String, String>);
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        Self(BUILTIN.iter().map(|&(ext, mime)| (ext.to_owned(), mime.to_owned())).collect())
+    }
+}
+
+impl MimeTypes {
+    pub fn set(self) {
+        let _ = MIME_TYPES.set(self);
+    }
+
+    pub fn get() -> &'static MimeTypes {
+        MIME_TYPES.get_or_init(MimeTypes::default)
+    }
+
+    // Add the types of a file in the format of /etc/mime.types, where each
+    // line holds a type followed by its extensions, replacing the types
+    // known for those extensions
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.read(BufReader::new(File::open(path)?))
+    }
+
+    fn read(&mut self, reader: impl BufRead) -> Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("");
+            let mut fields = line.split_whitespace();
+            if let Some(mime) = fields.next() {
+                for ext in fields {
+                    self.0.insert(ext.to_ascii_lowercase(), mime.to_owned());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The value of the Content-Type header for a file
+    pub fn of_file(&self, path: &Path) -> String {
+        let by_extension = path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| self.0.get(&ext.to_ascii_lowercase()));
+        let mime = match by_extension {
+            Some(mime) => mime.as_str(),
+            None => sniff_file(path).unwrap_or(OCTET_STREAM)
+        };
+        with_charset(mime)
+    }
+}
+
+
+// Whether a type (without parameters) is some kind of text
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || mime.ends_with("+json")
+        || matches!(mime, "application/json" | "application/xml" | "application/javascript")
+}
+
+
+// Text is served as UTF-8, which includes ASCII
+fn with_charset(mime: &str) -> String {
+    if is_text(mime) && !mime.contains(';') {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_owned()
+    }
+}
+
+
+fn sniff_file(path: &Path) -> Result<&'static str> {
+    let mut start = Vec::with_capacity(SNIFF_LENGTH);
+    File::open(path)?.take(SNIFF_LENGTH as u64).read_to_end(&mut start)?;
+    Ok(sniff(&start))
+}
+
+
+// Guess the type of a file from its first bytes, by their signature or by
+// whether they look like text
+fn sniff(start: &[u8]) -> &'static str {
+    if let Some(&(_, mime)) = SIGNATURES.iter().find(|(signature, _)| start.starts_with(signature)) {
+        return mime;
+    }
+    if start.len() >= 12 && &start[..4] == b"RIFF" && &start[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+
+    // The file may end in the middle of a character where it was cut off
+    let text = match str::from_utf8(start) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => str::from_utf8(&start[..e.valid_up_to()]).unwrap(),
+        Err(_) => return OCTET_STREAM
+    };
+    if text.chars().any(|c| c.is_control() && !c.is_ascii_whitespace()) {
+        return OCTET_STREAM;
+    }
+    let text = text.trim_start().to_ascii_lowercase();
+    if text.starts_with("<!doctype html") || text.starts_with("<html") {
+        "text/html"
+    } else if text.starts_with("<svg") || (text.starts_with("<?xml") && text.contains("<svg")) {
+        "image/svg+xml"
+    } else if text.starts_with("<?xml") {
+        "application/xml"
+    } else {
+        "text/plain"
+    }
+}
+
@@ -0,0 +1,153 @@
+// Parts of files requested with the Range header, which are sent with
+// "206 Partial Content", several of them as "multipart/byteranges"
+
+use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Cursor, Read, Result};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+
+
+// More ranges than this are likely meant to make the server do a lot of
+// work for little data, so the whole file is sent instead
+const MAX_RANGES: usize = 32;
+
+
+pub enum Ranges {
+    // The byte ranges to send, in order and without overlaps
+    Satisfiable(Vec<Range<u64>>),
+    // None of the ranges is part of the file
+    Unsatisfiable
+}
+
+// Parse the value of a Range header, like "bytes=0-499,1000-", for a file of
+// the given size. Returns None if the header has to be ignored, i.e. the
+// whole file is sent.
+pub fn parse(header: &str, size: u64) -> Option<Ranges> {
+    let (unit, specs) = header.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        let (first, last) = spec.split_once('-')?;
+        let range = match (first.trim(), last.trim()) {
+            // The last bytes of the file
+            ("", suffix) => {
+                let length: u64 = parse_number(suffix)?;
+                size.saturating_sub(length)..size
+            },
+            (first, "") => parse_number(first)?..size,
+            (first, last) => {
+                let (first, last) = (parse_number(first)?, parse_number(last)?);
+                if last < first {
+                    return None;
+                }
+                first..size.min(last.saturating_add(1))
+            }
+        };
+        if !range.is_empty() {
+            ranges.push(range);
+        }
+        if ranges.len() > MAX_RANGES {
+            return None;
+        }
+    }
+    if ranges.is_empty() {
+        return if specs.trim().is_empty() { None } else { Some(Ranges::Unsatisfiable) };
+    }
+
+    // Overlapping and adjacent ranges are sent as one
+    ranges.sort_unstable_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range)
+        }
+    }
+    Some(Ranges::Satisfiable(merged))
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+
+// The value of the Content-Range header for a range of a file
+pub fn content_range(range: &Range<u64>, size: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, size)
+}
+
+
+// The body of a "multipart/byteranges" response, whose parts are read from
+// the file while it is sent
+pub struct ByteRanges {
+    file: File,
+    parts: VecDeque<Part>
+}
+
+enum Part {
+    // The headers of a part, or the final boundary
+    Text(Cursor<Vec<u8>>),
+    // A range of the file, which is read from its start on
+    Range(Range<u64>)
+}
+
+impl ByteRanges {
+    // Return the body, its media type (with the boundary) and its length
+    pub fn new(file: File, ranges: &[Range<u64>], content_type: &str, size: u64) -> (Self, String, usize) {
+        // The boundary must not occur in the file, which is all but certain
+        // for a random one
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(size);
+        let boundary = format!("{:016x}", hasher.finish());
+
+        let mut parts = VecDeque::with_capacity(ranges.len() * 2 + 1);
+        let mut length = 0;
+        for range in ranges {
+            let headers = format!("\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                boundary, content_type, content_range(range, size));
+            length += headers.len() + (range.end - range.start) as usize;
+            parts.push_back(Part::Text(Cursor::new(headers.into_bytes())));
+            parts.push_back(Part::Range(range.clone()));
+        }
+        let end = format!("\r\n--{}--\r\n", boundary);
+        length += end.len();
+        parts.push_back(Part::Text(Cursor::new(end.into_bytes())));
+
+        (Self { file, parts }, format!("multipart/byteranges; boundary={}", boundary), length)
+    }
+}
+
+impl Read for ByteRanges {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while let Some(part) = self.parts.front_mut() {
+            let bytes_read = match part {
+                Part::Text(text) => text.read(buf)?,
+                Part::Range(range) => {
+                    let length = buf.len().min((range.end - range.start) as usize);
+                    let bytes_read = self.file.read_at(&mut buf[..length], range.start)?;
+                    range.start += bytes_read as u64;
+                    bytes_read
+                }
+            };
+            // An empty read at the end of a part moves on to the next one. A
+            // file which shrank ends the body early, which the client notices.
+            if bytes_read > 0 || buf.is_empty() {
+                return Ok(bytes_read);
+            }
+            if matches!(part, Part::Range(range) if !range.is_empty()) {
+                return Ok(0);
+            }
+            self.parts.pop_front();
+        }
+        Ok(0)
+    }
+}
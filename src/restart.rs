@@ -0,0 +1,132 @@
+// Handing the listening sockets over to a new process of the server, so it
+// can be upgraded without refusing any connections. The sockets are passed
+// like systemd passes them (starting at file descriptor 3), with variables
+// telling the new process how many belong to each server.
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::time::Duration;
+use micro_http_server::{Listener, MicroHTTP};
+use anyhow::{Error, bail};
+
+
+const HTTP_FDS: &str = "USER_SITES_HTTP_FDS";
+const HTTPS_FDS: &str = "USER_SITES_HTTPS_FDS";
+const READY_FD: &str = "USER_SITES_READY_FD";
+
+// The file descriptor of the first socket in the new process
+const FIRST_FD: RawFd = 3;
+
+// How long the new process may take until it accepts connections
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+// The sockets handed over by the previous process of the server
+pub struct Inherited {
+    pub http: Vec<Listener>,
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    pub https: Vec<Listener>,
+    // Told when this process accepts connections, so the previous one can
+    // stop accepting them
+    ready: UnixStream
+}
+
+impl Inherited {
+    // Take the sockets handed over to this process, if it was started by a
+    // previous process of the server
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let (http, https, ready) = match (env::var(HTTP_FDS), env::var(HTTPS_FDS), env::var(READY_FD)) {
+            (Ok(http), Ok(https), Ok(ready)) => (http.parse::<RawFd>()?, https.parse::<RawFd>()?, ready.parse()?),
+            _ => return Ok(None)
+        };
+        // Executables must not mistake the variables as meant for them
+        for name in &[HTTP_FDS, HTTPS_FDS, READY_FD] {
+            env::remove_var(name);
+        }
+
+        let take = |fds: std::ops::Range<RawFd>| {
+            fds.map(|fd| unsafe { Listener::from_inherited(fd) })
+                .collect::<io::Result<Vec<_>>>()
+        };
+        Ok(Some(Self {
+            http: take(FIRST_FD..FIRST_FD + http)?,
+            https: take(FIRST_FD + http..FIRST_FD + http + https)?,
+            ready: unsafe { UnixStream::from_raw_fd(ready) }
+        }))
+    }
+
+    // Tell the previous process that this one accepts connections now
+    pub fn ready(mut self) -> io::Result<()> {
+        self.ready.write_all(&[1])
+    }
+}
+
+
+// Start a new process of the server with the same arguments, handing over the
+// sockets of the servers, and wait until it accepts connections
+pub fn restart(http: &MicroHTTP, https: Option<&MicroHTTP>) -> Result<(), Error> {
+    let https_listeners = https.map(|s| s.listeners()).unwrap_or_default();
+    let (mut ready, new_ready) = UnixStream::pair()?;
+    let mut fds: Vec<RawFd> = http.listeners().iter()
+        .chain(https_listeners)
+        .map(|listener| listener.as_raw_fd())
+        .collect();
+    fds.push(new_ready.as_raw_fd());
+    let mut moved = vec![0; fds.len()];
+
+    let mut command = Command::new(env::current_exe()?);
+    command.args(env::args_os().skip(1))
+        .env(HTTP_FDS, http.listeners().len().to_string())
+        .env(HTTPS_FDS, https_listeners.len().to_string())
+        .env(READY_FD, (FIRST_FD + fds.len() as RawFd - 1).to_string());
+    unsafe {
+        command.pre_exec(move || pass_fds(&fds, &mut moved));
+    }
+    let mut child = command.spawn()?;
+    drop(new_ready);
+
+    // If the new process exits before it is ready, its end of the socket is
+    // closed and nothing can be read
+    ready.set_read_timeout(Some(READY_TIMEOUT))?;
+    match ready.read(&mut [0]) {
+        Ok(1) => Ok(()),
+        Ok(_) => {
+            drop(child.wait());
+            bail!("The new process exited before accepting connections")
+        },
+        Err(e) => {
+            // It must not accept connections later on, next to this process
+            drop(child.kill());
+            drop(child.wait());
+            bail!("The new process did not accept connections in time: {}", e)
+        }
+    }
+}
+
+
+// Move the file descriptors to FIRST_FD and the following ones, in order, so
+// they are inherited by the new process. This runs in the new process before
+// the server is executed, where only async-signal-safe functions may be used,
+// so `moved` has to be allocated beforehand.
+fn pass_fds(fds: &[RawFd], moved: &mut [RawFd]) -> io::Result<()> {
+    // First move them out of the way, so none of them is replaced before it
+    // was moved. These copies are closed when the server is executed.
+    let first_free = FIRST_FD + fds.len() as RawFd;
+    for (fd, moved) in fds.iter().zip(moved.iter_mut()) {
+        *moved = unsafe { libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, first_free) };
+        if *moved == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    // Unlike the copies, the new file descriptors are inherited
+    for (target, moved) in (FIRST_FD..).zip(moved.iter()) {
+        if unsafe { libc::dup2(*moved, target) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
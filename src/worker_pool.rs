@@ -0,0 +1,104 @@
+// A fixed number of threads handling jobs from a bounded queue
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::thread;
+use std::time::Instant;
+
+
+pub struct WorkerPool<T> {
+    // Taken once the pool stops taking jobs
+    sender: Mutex<Option<SyncSender<T>>>,
+    // Receives a message from each thread when it exits
+    finished: Mutex<Receiver<()>>,
+    workers: usize
+}
+
+impl<T> WorkerPool<T>
+where T: Send + 'static
+{
+    // Start `workers` threads which call `handler` on each job. At most
+    // `queue_size` jobs may wait for a thread to become available.
+    pub fn new<F>(workers: usize, queue_size: usize, handler: F) -> Self
+    where F: Fn(T) + Send + Sync + 'static
+    {
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+        let (finished_sender, finished) = mpsc::channel();
+
+        for _ in 0..workers {
+            let receiver = receiver.clone();
+            let handler = handler.clone();
+            let finished = Finished(finished_sender.clone());
+            thread::spawn(move || {
+                let _finished = finished;
+                work(&receiver, &*handler)
+            });
+        }
+
+        Self {
+            sender: Mutex::new(Some(sender)),
+            finished: Mutex::new(finished),
+            workers
+        }
+    }
+
+    // Queue a job to be handled by the next available thread. If the queue is
+    // full, or the pool doesn't take jobs anymore, the job is returned.
+    pub fn execute(&self, job: T) -> Result<(), T> {
+        match &*self.sender.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some(sender) => match sender.try_send(job) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(job)) | Err(TrySendError::Disconnected(job)) => Err(job)
+            },
+            None => Err(job)
+        }
+    }
+
+    // Stop taking jobs and wait until the jobs which are being handled or
+    // queued are done, but not past `deadline` if there is one. Returns whether
+    // they are done.
+    pub fn finish(&self, deadline: Option<Instant>) -> bool {
+        drop(self.sender.lock().unwrap_or_else(|e| e.into_inner()).take());
+        let finished = self.finished.lock().unwrap_or_else(|e| e.into_inner());
+        for _ in 0..self.workers {
+            let result = match deadline {
+                Some(deadline) => finished.recv_timeout(deadline.saturating_duration_since(Instant::now())).is_ok(),
+                None => finished.recv().is_ok()
+            };
+            if !result {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+
+// Tells the pool that a thread exited when dropped, even if the thread panicked
+struct Finished(Sender<()>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        let _ = self.0.send(());
+    }
+}
+
+
+fn work<T, F>(receiver: &Mutex<Receiver<T>>, handler: &F)
+where F: Fn(T)
+{
+    loop {
+        // Only hold the lock while waiting for a job, not while handling it
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => break
+        };
+
+        match job {
+            Ok(job) => handler(job),
+            Err(_) => break
+        }
+    }
+}